@@ -0,0 +1,12 @@
+fn main() -> std::io::Result<()> {
+    println!("cargo:rerun-if-changed=proto");
+    // Traces are sent over gRPC by opentelemetry-otlp's exporter, so only the metrics client is
+    // used. The messages are needed for all OTLP requests tracebuild encodes itself.
+    tonic_build::configure().build_server(false).compile(
+        &[
+            "proto/opentelemetry/proto/collector/trace/v1/trace_service.proto",
+            "proto/opentelemetry/proto/collector/metrics/v1/metrics_service.proto",
+        ],
+        &["proto"],
+    )
+}
@@ -0,0 +1,205 @@
+use opentelemetry::{Key, KeyValue};
+
+/// Build metadata detected from the environment variables of a known CI provider.
+#[derive(Debug, Default)]
+pub(crate) struct CiInfo {
+    pub(crate) provider: Option<&'static str>,
+    pub(crate) build_name: Option<String>,
+    pub(crate) branch: Option<String>,
+    pub(crate) commit: Option<String>,
+    pub(crate) run_url: Option<String>,
+    pub(crate) runner_os: Option<String>,
+    pub(crate) event: Option<String>,
+    /// Identifies the CI run, including the attempt if the provider reruns builds under the same
+    /// run id. Shared by all jobs of the run.
+    pub(crate) run_key: Option<String>,
+}
+
+impl CiInfo {
+    /// Span attributes describing the CI run. Branch and commit are not included, because they
+    /// are reported as part of the build span.
+    pub(crate) fn attributes(&self) -> Vec<KeyValue> {
+        let mut attributes = Vec::new();
+        if let Some(provider) = self.provider {
+            attributes.push(Key::new("tracebuild.ci.provider").string(provider));
+        }
+        if let Some(run_url) = self.run_url.clone() {
+            attributes.push(Key::new("tracebuild.ci.run_url").string(run_url));
+        }
+        if let Some(runner_os) = self.runner_os.clone() {
+            attributes.push(Key::new("tracebuild.ci.runner_os").string(runner_os));
+        }
+        if let Some(event) = self.event.clone() {
+            attributes.push(Key::new("tracebuild.ci.event").string(event));
+        }
+        attributes
+    }
+
+    /// A stable string identifying the CI run, which can be used as a seed for build ids.
+    pub(crate) fn run_seed(&self) -> Option<String> {
+        Some(format!("{}/{}", self.provider?, self.run_key.as_ref()?))
+    }
+}
+
+/// Detects the CI provider tracebuild runs in. Returns empty info if none was detected.
+pub(crate) fn detect() -> CiInfo {
+    if var("GITHUB_ACTIONS").is_some() {
+        github_actions()
+    } else if var("GITLAB_CI").is_some() {
+        gitlab_ci()
+    } else if var("TRAVIS").is_some() {
+        travis()
+    } else if var("CIRCLECI").is_some() {
+        circleci()
+    } else if var("TF_BUILD").is_some() {
+        azure_pipelines()
+    } else if var("BUILDKITE").is_some() {
+        buildkite()
+    } else if var("JENKINS_URL").is_some() {
+        jenkins()
+    } else {
+        CiInfo::default()
+    }
+}
+
+fn github_actions() -> CiInfo {
+    let run_url = match (
+        var("GITHUB_SERVER_URL"),
+        var("GITHUB_REPOSITORY"),
+        var("GITHUB_RUN_ID"),
+    ) {
+        (Some(server), Some(repository), Some(run_id)) => {
+            Some(format!("{}/{}/actions/runs/{}", server, repository, run_id))
+        }
+        _ => None,
+    };
+    CiInfo {
+        provider: Some("github_actions"),
+        build_name: var("GITHUB_WORKFLOW"),
+        branch: var("GITHUB_HEAD_REF").or_else(|| branch_from_ref(var("GITHUB_REF"))),
+        commit: var("GITHUB_SHA"),
+        run_url,
+        runner_os: var("RUNNER_OS"),
+        event: var("GITHUB_EVENT_NAME"),
+        run_key: var("GITHUB_RUN_ID").map(|run_id| {
+            format!(
+                "{}/{}",
+                run_id,
+                var("GITHUB_RUN_ATTEMPT").unwrap_or_else(|| "1".into())
+            )
+        }),
+    }
+}
+
+fn gitlab_ci() -> CiInfo {
+    CiInfo {
+        provider: Some("gitlab_ci"),
+        build_name: var("CI_PROJECT_PATH"),
+        branch: var("CI_MERGE_REQUEST_SOURCE_BRANCH_NAME").or_else(|| var("CI_COMMIT_BRANCH")),
+        commit: var("CI_COMMIT_SHA"),
+        run_url: var("CI_PIPELINE_URL"),
+        // Has the format "linux/amd64".
+        runner_os: var("CI_RUNNER_EXECUTABLE_ARCH")
+            .and_then(|arch| arch.split('/').next().map(String::from)),
+        event: var("CI_PIPELINE_SOURCE"),
+        run_key: var("CI_PIPELINE_ID"),
+    }
+}
+
+fn travis() -> CiInfo {
+    CiInfo {
+        provider: Some("travis"),
+        build_name: var("TRAVIS_REPO_SLUG"),
+        branch: var("TRAVIS_PULL_REQUEST_BRANCH").or_else(|| var("TRAVIS_BRANCH")),
+        commit: var("TRAVIS_COMMIT"),
+        run_url: var("TRAVIS_BUILD_WEB_URL"),
+        runner_os: var("TRAVIS_OS_NAME"),
+        event: var("TRAVIS_EVENT_TYPE"),
+        run_key: var("TRAVIS_BUILD_ID"),
+    }
+}
+
+fn circleci() -> CiInfo {
+    CiInfo {
+        provider: Some("circleci"),
+        build_name: var("CIRCLE_PROJECT_REPONAME"),
+        branch: var("CIRCLE_BRANCH"),
+        commit: var("CIRCLE_SHA1"),
+        run_url: var("CIRCLE_BUILD_URL"),
+        runner_os: Some(std::env::consts::OS.into()),
+        event: None,
+        // Reruns create a new workflow.
+        run_key: var("CIRCLE_WORKFLOW_ID"),
+    }
+}
+
+fn azure_pipelines() -> CiInfo {
+    let run_url = match (
+        var("SYSTEM_COLLECTIONURI"),
+        var("SYSTEM_TEAMPROJECT"),
+        var("BUILD_BUILDID"),
+    ) {
+        (Some(collection), Some(project), Some(build_id)) => Some(format!(
+            "{}/{}/_build/results?buildId={}",
+            collection.trim_end_matches('/'),
+            project,
+            build_id
+        )),
+        _ => None,
+    };
+    CiInfo {
+        provider: Some("azure_pipelines"),
+        build_name: var("BUILD_DEFINITIONNAME"),
+        branch: branch_from_ref(var("SYSTEM_PULLREQUEST_SOURCEBRANCH"))
+            .or_else(|| branch_from_ref(var("BUILD_SOURCEBRANCH"))),
+        commit: var("BUILD_SOURCEVERSION"),
+        run_url,
+        runner_os: var("AGENT_OS"),
+        event: var("BUILD_REASON"),
+        run_key: var("BUILD_BUILDID"),
+    }
+}
+
+fn buildkite() -> CiInfo {
+    CiInfo {
+        provider: Some("buildkite"),
+        build_name: var("BUILDKITE_PIPELINE_SLUG"),
+        branch: var("BUILDKITE_BRANCH"),
+        // Buildkite uses "HEAD" for builds, which have not been resolved to a commit, yet.
+        commit: var("BUILDKITE_COMMIT").filter(|commit| commit != "HEAD"),
+        run_url: var("BUILDKITE_BUILD_URL"),
+        runner_os: Some(std::env::consts::OS.into()),
+        event: var("BUILDKITE_SOURCE"),
+        run_key: var("BUILDKITE_BUILD_ID"),
+    }
+}
+
+fn jenkins() -> CiInfo {
+    CiInfo {
+        provider: Some("jenkins"),
+        build_name: var("JOB_NAME"),
+        branch: var("CHANGE_BRANCH")
+            .or_else(|| var("BRANCH_NAME"))
+            .or_else(|| {
+                var("GIT_BRANCH").map(|branch| branch.trim_start_matches("origin/").to_string())
+            }),
+        commit: var("GIT_COMMIT"),
+        run_url: var("BUILD_URL"),
+        runner_os: Some(std::env::consts::OS.into()),
+        event: None,
+        // Build numbers are only unique per job.
+        run_key: var("BUILD_URL"),
+    }
+}
+
+/// Returns the branch name of a git ref like "refs/heads/main". Other refs like tags or pull
+/// request merge refs don't have a branch.
+fn branch_from_ref(git_ref: Option<String>) -> Option<String> {
+    git_ref?.strip_prefix("refs/heads/").map(String::from)
+}
+
+/// Reads an environment variable. Empty values are treated as unset, because CI providers tend
+/// to set variables to empty strings when they don't apply.
+fn var(name: &str) -> Option<String> {
+    std::env::var(name).ok().filter(|value| !value.is_empty())
+}
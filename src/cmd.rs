@@ -1,14 +1,26 @@
-use std::{io, process::ExitStatus};
+use crate::output::{CaptureConfig, CapturedOutput, OutputTee};
+#[cfg(unix)]
+use crate::pty::{Pty, WindowSizeForwarder};
+use std::{
+    io,
+    process::{ExitStatus, Stdio},
+    time::Duration,
+};
 use thiserror::Error;
-use tokio::process::{Child, Command};
+#[cfg(unix)]
+use tokio::process::Child;
+use tokio::process::Command;
 
 #[derive(Debug, Error)]
 pub(crate) enum ForkError {
     #[error("Failed to fork child program: {0}")]
     FailedToFork(io::Error),
     #[cfg(unix)]
-    #[error("Failed to register SIGTERM handler: {0}")]
+    #[error("Failed to register signal handler: {0}")]
     FailedToRegisterSignalHandler(io::Error),
+    #[cfg(unix)]
+    #[error("Failed to open pseudo-terminal: {0}")]
+    FailedToOpenPty(io::Error),
     #[error("Child program failed: {0}")]
     IoError(#[from] io::Error),
     #[cfg(not(unix))]
@@ -25,6 +37,8 @@ impl ForkError {
             ForkError::FailedToFork(_) => EX_OSERR,
             #[cfg(unix)]
             ForkError::FailedToRegisterSignalHandler(_) => EX_OSERR,
+            #[cfg(unix)]
+            ForkError::FailedToOpenPty(_) => EX_OSERR,
             ForkError::IoError(err) => err.raw_os_error().unwrap_or(1),
             #[cfg(not(unix))]
             ForkError::Killed => 1,
@@ -32,77 +46,434 @@ impl ForkError {
     }
 }
 
-struct TermSignal {
-    #[cfg(unix)]
-    signal: tokio::signal::unix::Signal,
+/// Resource usage of all terminated and waited for children, i.e. the forked command including
+/// its descendants.
+#[derive(Debug)]
+pub(crate) struct ResourceUsage {
+    pub(crate) user_time: Duration,
+    pub(crate) system_time: Duration,
+    /// Maximum resident set size in bytes.
+    pub(crate) max_rss: i64,
+    pub(crate) minor_page_faults: i64,
+    pub(crate) major_page_faults: i64,
+    pub(crate) block_input_ops: i64,
+    pub(crate) block_output_ops: i64,
+    pub(crate) voluntary_context_switches: i64,
+    pub(crate) involuntary_context_switches: i64,
+}
+
+#[cfg(unix)]
+pub(crate) fn children_resource_usage() -> Option<ResourceUsage> {
+    fn to_duration(time: libc::timeval) -> Duration {
+        Duration::new(time.tv_sec as u64, time.tv_usec as u32 * 1000)
+    }
+
+    let mut usage = std::mem::MaybeUninit::<libc::rusage>::uninit();
+    // SAFETY: getrusage initializes the struct if it returns 0.
+    let usage = unsafe {
+        if libc::getrusage(libc::RUSAGE_CHILDREN, usage.as_mut_ptr()) != 0 {
+            return None;
+        }
+        usage.assume_init()
+    };
+
+    // macOS reports the max RSS in bytes, all others in kilobytes.
+    let max_rss_unit = if cfg!(target_os = "macos") { 1 } else { 1024 };
+
+    Some(ResourceUsage {
+        user_time: to_duration(usage.ru_utime),
+        system_time: to_duration(usage.ru_stime),
+        max_rss: usage.ru_maxrss as i64 * max_rss_unit,
+        minor_page_faults: usage.ru_minflt as i64,
+        major_page_faults: usage.ru_majflt as i64,
+        block_input_ops: usage.ru_inblock as i64,
+        block_output_ops: usage.ru_oublock as i64,
+        voluntary_context_switches: usage.ru_nvcsw as i64,
+        involuntary_context_switches: usage.ru_nivcsw as i64,
+    })
+}
+
+#[cfg(not(unix))]
+pub(crate) fn children_resource_usage() -> Option<ResourceUsage> {
+    None
+}
+
+/// Signal, which terminated the child.
+#[derive(Debug)]
+pub(crate) struct TerminationSignal {
+    pub(crate) number: i32,
+    /// Name like "SIGSEGV", if the signal is known.
+    pub(crate) name: Option<&'static str>,
+    pub(crate) core_dumped: bool,
 }
 
 #[cfg(unix)]
-impl TermSignal {
+pub(crate) fn termination_signal(exit_status: &ExitStatus) -> Option<TerminationSignal> {
+    use nix::sys::signal::Signal;
+    use std::{convert::TryFrom as _, os::unix::process::ExitStatusExt as _};
+
+    let number = exit_status.signal()?;
+    Some(TerminationSignal {
+        number,
+        name: Signal::try_from(number).ok().map(Signal::as_str),
+        core_dumped: exit_status.core_dumped(),
+    })
+}
+
+#[cfg(not(unix))]
+pub(crate) fn termination_signal(_exit_status: &ExitStatus) -> Option<TerminationSignal> {
+    None
+}
+
+/// How the child exited.
+#[derive(Debug)]
+pub(crate) struct ChildExit {
+    pub(crate) status: ExitStatus,
+    /// Name of the signal, which tracebuild received and forwarded to cancel the child.
+    pub(crate) cancelled_by: Option<&'static str>,
+    /// Whether the child was terminated, because it didn't exit before the timeout.
+    pub(crate) timed_out: bool,
+    /// Whether the child was killed, because it didn't exit within the grace period after it was
+    /// cancelled or timed out.
+    pub(crate) killed: bool,
+    /// Captured output, if enabled.
+    pub(crate) output: Option<CapturedOutput>,
+}
+
+/// The shell used to run scripts: $SHELL or sh if unset.
+pub(crate) fn shell() -> String {
+    std::env::var("SHELL")
+        .ok()
+        .filter(|shell| !shell.is_empty())
+        .unwrap_or_else(|| "sh".into())
+}
+
+async fn sleep_until(deadline: Option<tokio::time::Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
+}
+
+#[cfg(unix)]
+struct Signals {
+    hangup: tokio::signal::unix::Signal,
+    interrupt: tokio::signal::unix::Signal,
+    quit: tokio::signal::unix::Signal,
+    terminate: tokio::signal::unix::Signal,
+    user_defined1: tokio::signal::unix::Signal,
+    user_defined2: tokio::signal::unix::Signal,
+}
+
+#[cfg(unix)]
+impl Signals {
     fn new() -> Result<Self, ForkError> {
         use tokio::signal::unix::{signal, SignalKind};
 
-        let signal =
-            signal(SignalKind::terminate()).map_err(ForkError::FailedToRegisterSignalHandler)?;
-        Ok(Self { signal })
+        let register = |kind| signal(kind).map_err(ForkError::FailedToRegisterSignalHandler);
+        Ok(Self {
+            hangup: register(SignalKind::hangup())?,
+            interrupt: register(SignalKind::interrupt())?,
+            quit: register(SignalKind::quit())?,
+            terminate: register(SignalKind::terminate())?,
+            user_defined1: register(SignalKind::user_defined1())?,
+            user_defined2: register(SignalKind::user_defined2())?,
+        })
     }
 
-    async fn recv(&mut self) {
-        self.signal.recv().await;
+    async fn recv(&mut self) -> nix::sys::signal::Signal {
+        use nix::sys::signal::Signal;
+
+        tokio::select! {
+            _ = self.hangup.recv() => Signal::SIGHUP,
+            _ = self.interrupt.recv() => Signal::SIGINT,
+            _ = self.quit.recv() => Signal::SIGQUIT,
+            _ = self.terminate.recv() => Signal::SIGTERM,
+            _ = self.user_defined1.recv() => Signal::SIGUSR1,
+            _ = self.user_defined2.recv() => Signal::SIGUSR2,
+        }
     }
 }
 
-#[cfg(not(unix))]
-impl TermSignal {
-    #[allow(clippy::unnecessary_wraps)]
-    fn new() -> Result<Self, ForkError> {
-        Ok(Self {})
+#[cfg(unix)]
+async fn recv_window_size_change(forwarder: &mut Option<WindowSizeForwarder>) -> io::Result<()> {
+    match forwarder {
+        Some(forwarder) => forwarder.recv().await,
+        None => std::future::pending().await,
     }
+}
 
-    async fn recv(&mut self) {
-        tokio::signal::ctrl_c()
-            .await
-            .expect("failed to listen for ctrl-c")
+/// Sends the signal to the child's process group, which includes all its descendants unless they
+/// moved to their own group.
+#[cfg(unix)]
+fn terminate_child(child: &Child, signal: nix::sys::signal::Signal) {
+    use nix::{sys::signal::killpg, unistd::Pid};
+    use std::convert::TryInto as _;
+
+    // If the child has already completed, there is nobody left to signal.
+    if let Some(pid) = child.id() {
+        if let Err(e) = killpg(Pid::from_raw(pid.try_into().expect("Invalid PID")), signal) {
+            eprintln!("Failed to forward {} to child process: {}", signal, e);
+        }
     }
 }
 
+/// Runs in the forked child before exec. Puts the child in its own process group, so signals can
+/// be forwarded to all its descendants. With `foreground`, the new group also takes over the
+/// terminal, so the child can read from it.
 #[cfg(unix)]
-async fn terminate_child(mut child: Child) -> Result<ExitStatus, ForkError> {
+fn setup_child_process_group(foreground: bool) -> io::Result<()> {
+    use nix::unistd::{setpgid, Pid};
+
+    setpgid(Pid::from_raw(0), Pid::from_raw(0)).map_err(nix_to_io_error)?;
+    if foreground {
+        set_terminal_foreground_process_group()?;
+    }
+    Ok(())
+}
+
+/// Whether the calling process' group is the foreground group of the terminal attached to stdin.
+/// Jobs running in the background of a shell must leave the terminal to the foreground job.
+#[cfg(unix)]
+fn is_terminal_foreground_process_group() -> bool {
+    use nix::unistd::{getpgrp, tcgetpgrp};
+
+    tcgetpgrp(libc::STDIN_FILENO) == Ok(getpgrp())
+}
+
+/// Makes the calling process' group the foreground group of the terminal attached to stdin.
+#[cfg(unix)]
+fn set_terminal_foreground_process_group() -> io::Result<()> {
     use nix::{
-        sys::signal::{kill, Signal::SIGTERM},
-        unistd::Pid,
+        sys::signal::{SigSet, Signal},
+        unistd::{getpgrp, tcsetpgrp},
     };
-    use std::convert::TryInto as _;
 
-    if let Some(pid) = child.id() {
-        // If the child hasn't already completed, send a SIGTERM.
-        if let Err(e) = kill(Pid::from_raw(pid.try_into().expect("Invalid PID")), SIGTERM) {
-            eprintln!("Failed to forward SIGTERM to child process: {}", e);
+    // Background process groups get a SIGTTOU when they change the foreground group, unless they
+    // block it.
+    let mut sigttou = SigSet::empty();
+    sigttou.add(Signal::SIGTTOU);
+    sigttou.thread_block().map_err(nix_to_io_error)?;
+    let result = tcsetpgrp(libc::STDIN_FILENO, getpgrp()).map_err(nix_to_io_error);
+    sigttou.thread_unblock().map_err(nix_to_io_error)?;
+    result
+}
+
+#[cfg(unix)]
+pub(crate) fn nix_to_io_error(err: nix::Error) -> io::Error {
+    match err {
+        nix::Error::Sys(errno) => io::Error::from_raw_os_error(errno as i32),
+        nix::Error::UnsupportedOperation => io::Error::new(io::ErrorKind::Unsupported, err),
+        nix::Error::InvalidPath | nix::Error::InvalidUtf8 => {
+            io::Error::new(io::ErrorKind::InvalidInput, err)
         }
     }
-    // Wait to get the child's exit code.
-    child.wait().await.map_err(Into::into)
 }
 
-#[cfg(not(unix))]
-async fn terminate_child(mut child: Child) -> Result<ExitStatus, ForkError> {
-    child.kill().await?;
-    Err(ForkError::Killed)
+/// Runs the command and forwards SIGHUP, SIGINT, SIGQUIT, SIGTERM, SIGUSR1 and SIGUSR2 to it.
+/// All signals except SIGUSR1 and SIGUSR2 cancel the command: if it doesn't exit within the grace
+/// period afterwards, it gets killed. If the command runs longer than the timeout, it gets a
+/// SIGTERM and the same grace period.
+///
+/// If the capture config asks for a pseudo-terminal, the child's stdout and stderr are connected
+/// to a new pseudo-terminal, which follows the window size of tracebuild's terminal. Stdin is
+/// still inherited.
+///
+/// The child only becomes the foreground job of tracebuild's terminal with `foreground`, and only
+/// if tracebuild is the foreground job itself. The terminal then sends Ctrl-C to the child instead
+/// of tracebuild. Otherwise the child runs in the background and reading from the terminal stops
+/// it.
+#[cfg(unix)]
+pub(crate) async fn fork_and_forward_signals(
+    cmd: String,
+    args: Vec<String>,
+    envs: Vec<(&'static str, String)>,
+    timeout: Option<Duration>,
+    grace_period: Duration,
+    capture: Option<CaptureConfig>,
+    foreground: bool,
+) -> Result<ChildExit, ForkError> {
+    use nix::sys::signal::Signal;
+
+    let mut command = Command::new(&cmd);
+    command.args(args).envs(envs);
+    let mut pty = None;
+    let mut window_size_forwarder = None;
+    match &capture {
+        Some(config) if config.pty => {
+            let new_pty = Pty::open().map_err(ForkError::FailedToOpenPty)?;
+            let slave_stdio = || new_pty.slave_stdio().map_err(ForkError::FailedToOpenPty);
+            command.stdout(slave_stdio()?).stderr(slave_stdio()?);
+            window_size_forwarder = Some(
+                new_pty
+                    .forward_window_size()
+                    .map_err(ForkError::FailedToRegisterSignalHandler)?,
+            );
+            pty = Some(new_pty);
+        }
+        Some(_) => {
+            command.stdout(Stdio::piped()).stderr(Stdio::piped());
+        }
+        None => {}
+    }
+    let foreground = foreground && is_terminal_foreground_process_group();
+    // SAFETY: setup_child_process_group only calls async-signal-safe functions.
+    unsafe {
+        command.pre_exec(move || setup_child_process_group(foreground));
+    }
+    let mut signals = Signals::new()?;
+    let mut child = command.spawn().map_err(ForkError::FailedToFork)?;
+    // The command keeps handles to the slave side of the pseudo-terminal.
+    drop(command);
+    let tee = capture.map(|config| match pty {
+        Some(pty) => OutputTee::start_pty(pty.into_master(), config),
+        None => OutputTee::start(&mut child, config),
+    });
+
+    let mut timeout_deadline = timeout.map(|timeout| tokio::time::Instant::now() + timeout);
+    let mut cancelled_by = None;
+    let mut timed_out = false;
+    let mut kill_deadline = None;
+    let mut killed = false;
+    let status = loop {
+        tokio::select! {
+            status = child.wait() => break status,
+            signal = signals.recv() => {
+                terminate_child(&child, signal);
+                if cancelled_by.is_none() && signal != Signal::SIGUSR1 && signal != Signal::SIGUSR2 {
+                    cancelled_by = Some(signal.as_str());
+                    kill_deadline = kill_deadline
+                        .or_else(|| Some(tokio::time::Instant::now() + grace_period));
+                }
+            }
+            _ = sleep_until(timeout_deadline) => {
+                terminate_child(&child, Signal::SIGTERM);
+                timeout_deadline = None;
+                timed_out = true;
+                kill_deadline = kill_deadline
+                    .or_else(|| Some(tokio::time::Instant::now() + grace_period));
+            }
+            _ = sleep_until(kill_deadline) => {
+                terminate_child(&child, Signal::SIGKILL);
+                kill_deadline = None;
+                killed = true;
+            }
+            result = recv_window_size_change(&mut window_size_forwarder) => match result {
+                Ok(()) => terminate_child(&child, Signal::SIGWINCH),
+                Err(err) => {
+                    eprintln!("Failed to copy terminal window size: {}", err);
+                    window_size_forwarder = None;
+                }
+            },
+        }
+    };
+
+    // The child's process group no longer exists. Take the terminal back, if it was handed over.
+    if foreground {
+        if let Err(err) = set_terminal_foreground_process_group() {
+            eprintln!(
+                "Failed to restore terminal foreground process group: {}",
+                err
+            );
+        }
+    }
+
+    let output = match tee {
+        Some(tee) => Some(tee.finish().await),
+        None => None,
+    };
+
+    Ok(ChildExit {
+        status: status?,
+        cancelled_by,
+        timed_out,
+        killed,
+        output,
+    })
 }
 
-pub(crate) async fn fork_with_sigterm(
+#[cfg(not(unix))]
+pub(crate) async fn fork_and_forward_signals(
     cmd: String,
     args: Vec<String>,
-) -> Result<ExitStatus, ForkError> {
-    let mut child = Command::new(&cmd)
-        .args(args)
-        .spawn()
-        .map_err(ForkError::FailedToFork)?;
+    envs: Vec<(&'static str, String)>,
+    timeout: Option<Duration>,
+    _grace_period: Duration,
+    capture: Option<CaptureConfig>,
+    _foreground: bool,
+) -> Result<ChildExit, ForkError> {
+    if matches!(&capture, Some(config) if config.pty) {
+        return Err(ForkError::FailedToFork(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "pseudo-terminals are only supported on unix",
+        )));
+    }
+
+    let mut command = Command::new(&cmd);
+    command.args(args).envs(envs);
+    if capture.is_some() {
+        command.stdout(Stdio::piped()).stderr(Stdio::piped());
+    }
+    let mut child = command.spawn().map_err(ForkError::FailedToFork)?;
+    let tee = capture.map(|config| OutputTee::start(&mut child, config));
+
+    let timeout_deadline = timeout.map(|timeout| tokio::time::Instant::now() + timeout);
+    let (status, timed_out) = tokio::select! {
+        status = child.wait() => (status?, false),
+        _ = sleep_until(timeout_deadline) => {
+            child.kill().await?;
+            (child.wait().await?, true)
+        }
+        _ = tokio::signal::ctrl_c() => {
+            child.kill().await?;
+            return Err(ForkError::Killed);
+        }
+    };
 
-    let mut sigterm = TermSignal::new()?;
+    let output = match tee {
+        Some(tee) => Some(tee.finish().await),
+        None => None,
+    };
+
+    Ok(ChildExit {
+        status,
+        cancelled_by: None,
+        timed_out,
+        killed: timed_out,
+        output,
+    })
+}
+
+/// Waits for the given time, unless tracebuild receives a SIGHUP, SIGINT, SIGQUIT or SIGTERM
+/// (Ctrl-C on other platforms) in the meantime. Returns the name of the received signal.
+pub(crate) async fn sleep_unless_cancelled(duration: Duration) -> Option<&'static str> {
+    #[cfg(unix)]
+    let cancelled = async {
+        use nix::sys::signal::Signal;
+
+        match Signals::new() {
+            Ok(mut signals) => loop {
+                let signal = signals.recv().await;
+                if signal != Signal::SIGUSR1 && signal != Signal::SIGUSR2 {
+                    return signal.as_str();
+                }
+            },
+            Err(err) => {
+                eprintln!("{}", err);
+                std::future::pending().await
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let cancelled = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("failed to listen for ctrl-c");
+        "CTRL_C"
+    };
 
     tokio::select! {
-        ex = child.wait() => ex.map_err(Into::into),
-        _ = sigterm.recv() => terminate_child(child).await
+        _ = tokio::time::sleep(duration) => None,
+        signal = cancelled => Some(signal),
     }
 }
@@ -1,16 +1,29 @@
 use crate::id::{BuildId, StepId};
 use opentelemetry::{
-    trace::{TraceContextExt as _, TRACE_FLAG_SAMPLED},
+    trace::{SpanContext, TraceContextExt as _, TRACE_FLAG_SAMPLED},
     Context,
 };
 
-pub(crate) fn get_parent_context(build: BuildId, step: Option<StepId>) -> Context {
-    let span_context = opentelemetry::trace::SpanContext::new(
+pub(crate) fn get_parent_span_context(build: BuildId, step: Option<StepId>) -> SpanContext {
+    SpanContext::new(
         build.trace_id(),
         step.map(|s| s.span_id()).unwrap_or_else(|| build.span_id()),
         TRACE_FLAG_SAMPLED,
         true,
         Default::default(),
-    );
-    Context::current().with_remote_span_context(span_context)
+    )
+}
+
+pub(crate) fn get_parent_context(build: BuildId, step: Option<StepId>) -> Context {
+    Context::current().with_remote_span_context(get_parent_span_context(build, step))
+}
+
+/// Formats the span context as a W3C traceparent header value.
+pub(crate) fn traceparent(span_context: &SpanContext) -> String {
+    format!(
+        "00-{:032x}-{:016x}-{:02x}",
+        span_context.trace_id().to_u128(),
+        span_context.span_id().to_u64(),
+        span_context.trace_flags() & TRACE_FLAG_SAMPLED
+    )
 }
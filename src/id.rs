@@ -1,8 +1,11 @@
-use opentelemetry::trace::{SpanId, TraceId};
+use opentelemetry::trace::{SpanContext, SpanId, TraceId};
 use rand::prelude::*;
+use sha2::{Digest as _, Sha256};
+use std::convert::TryInto as _;
 use std::fmt::Display;
 use std::str::FromStr;
 
+#[derive(Clone)]
 pub(crate) struct BuildId {
     trace: u128,
     span: u64,
@@ -16,6 +19,57 @@ impl BuildId {
         }
     }
 
+    /// Derives the ID from the SHA-256 hash of the given seed. The same seed always results in
+    /// the same ID.
+    pub(crate) fn from_seed(seed: &str) -> Self {
+        let hash = Sha256::digest(seed.as_bytes());
+        Self {
+            trace: u128::from_be_bytes(hash[..16].try_into().expect("hash has 32 bytes")),
+            span: u64::from_be_bytes(hash[16..24].try_into().expect("hash has 32 bytes")),
+        }
+    }
+
+    /// Returns a new ID with the same span id, but the trace id of the given parent.
+    pub(crate) fn with_trace_of(self, parent: &BuildId) -> Self {
+        Self {
+            trace: parent.trace,
+            span: self.span,
+        }
+    }
+
+    /// Parses a W3C traceparent header value, e.g.
+    /// "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01".
+    fn from_traceparent(s: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let parts = s.split('-').collect::<Vec<_>>();
+        // Future versions may append more fields.
+        let (version, s_trace, s_span, flags) = match parts.as_slice() {
+            [version, trace, span, flags] => (*version, *trace, *span, *flags),
+            [version, trace, span, flags, ..] if *version != "00" => {
+                (*version, *trace, *span, *flags)
+            }
+            _ => return Err("traceparent does not have 4 parts".into()),
+        };
+        if version.len() != 2 || version == "ff" {
+            return Err("traceparent version is invalid".into());
+        }
+        if s_trace.len() != 32 || s_span.len() != 16 || flags.len() != 2 {
+            return Err("traceparent trace id, span id or flags have invalid length".into());
+        }
+        if ![version, s_trace, s_span, flags]
+            .iter()
+            .all(|part| is_lower_hex(part))
+        {
+            return Err("traceparent is not lowercase hex".into());
+        }
+
+        let trace = u128::from_str_radix(s_trace, 16)?;
+        let span = u64::from_str_radix(s_span, 16)?;
+        if trace == 0 || span == 0 {
+            return Err("traceparent trace id or span id is all zeros".into());
+        }
+        Ok(Self { trace, span })
+    }
+
     pub(crate) fn trace_id(&self) -> TraceId {
         TraceId::from_u128(self.trace)
     }
@@ -25,10 +79,19 @@ impl BuildId {
     }
 }
 
+fn is_lower_hex(s: &str) -> bool {
+    s.bytes()
+        .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+}
+
 impl FromStr for BuildId {
     type Err = Box<dyn std::error::Error>;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.contains('-') {
+            return Self::from_traceparent(s);
+        }
+
         if s.len() != 48 {
             return Err("string len is not 48".into());
         }
@@ -46,12 +109,43 @@ impl Display for BuildId {
     }
 }
 
+/// Output format for IDs.
+pub(crate) enum IdFormat {
+    /// 32 hex chars trace id followed by 16 hex chars span id.
+    Tracebuild,
+    /// W3C traceparent header value.
+    Traceparent,
+}
+
+impl FromStr for IdFormat {
+    type Err = Box<dyn std::error::Error>;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tracebuild" => Ok(IdFormat::Tracebuild),
+            "traceparent" => Ok(IdFormat::Traceparent),
+            _ => Err("invalid format; valid are: tracebuild, traceparent".into()),
+        }
+    }
+}
+
 pub(crate) struct StepId(BuildId);
 
 impl StepId {
+    pub(crate) fn from_span_context(span_context: &SpanContext) -> Self {
+        Self(BuildId {
+            trace: span_context.trace_id().to_u128(),
+            span: span_context.span_id().to_u64(),
+        })
+    }
+
     pub(crate) fn span_id(&self) -> SpanId {
         self.0.span_id()
     }
+
+    pub(crate) fn build_id(&self) -> &BuildId {
+        &self.0
+    }
 }
 
 impl FromStr for StepId {
@@ -67,3 +161,104 @@ impl Display for StepId {
         self.0.fmt(f)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_tracebuild_format() {
+        let id: BuildId = "0af7651916cd43dd8448eb211c80319cb7ad6b7169203331"
+            .parse()
+            .unwrap();
+        assert_eq!(id.trace, 0x0af7651916cd43dd8448eb211c80319c);
+        assert_eq!(id.span, 0xb7ad6b7169203331);
+        assert_eq!(
+            id.to_string(),
+            "0af7651916cd43dd8448eb211c80319cb7ad6b7169203331"
+        );
+    }
+
+    #[test]
+    fn parses_traceparent() {
+        for (traceparent, valid) in [
+            (
+                "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01",
+                true,
+            ),
+            (
+                "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-00",
+                true,
+            ),
+            // Future versions may append fields.
+            (
+                "cc-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01-xyz",
+                true,
+            ),
+            (
+                "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01-xyz",
+                false,
+            ),
+            (
+                "ff-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01",
+                false,
+            ),
+            (
+                "00-0AF7651916CD43DD8448EB211C80319C-B7AD6B7169203331-01",
+                false,
+            ),
+            (
+                "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-0A",
+                false,
+            ),
+            (
+                "00-+af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01",
+                false,
+            ),
+            (
+                "00-00000000000000000000000000000000-b7ad6b7169203331-01",
+                false,
+            ),
+            (
+                "00-0af7651916cd43dd8448eb211c80319c-0000000000000000-01",
+                false,
+            ),
+            (
+                "00-0af7651916cd43dd8448eb211c80319-b7ad6b7169203331-01",
+                false,
+            ),
+            (
+                "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331",
+                false,
+            ),
+        ] {
+            let result = traceparent.parse::<BuildId>();
+            assert_eq!(result.is_ok(), valid, "{}", traceparent);
+            if let Ok(id) = result {
+                assert_eq!(id.trace, 0x0af7651916cd43dd8448eb211c80319c);
+                assert_eq!(id.span, 0xb7ad6b7169203331);
+            }
+        }
+    }
+
+    #[test]
+    fn derives_ids_from_seeds() {
+        let id = BuildId::from_seed("github_actions/1/1");
+        assert_eq!(
+            id.to_string(),
+            BuildId::from_seed("github_actions/1/1").to_string()
+        );
+        assert_ne!(
+            id.to_string(),
+            BuildId::from_seed("github_actions/1/2").to_string()
+        );
+    }
+
+    #[test]
+    fn keeps_span_id_with_trace_of_parent() {
+        let parent = BuildId::from_seed("parent");
+        let id = BuildId::from_seed("build").with_trace_of(&parent);
+        assert_eq!(id.trace_id(), parent.trace_id());
+        assert_eq!(id.span_id(), BuildId::from_seed("build").span_id());
+    }
+}
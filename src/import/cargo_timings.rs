@@ -0,0 +1,231 @@
+use super::{ImportError, ImportedSpan, Recorder};
+use opentelemetry::{global::BoxedTracer, Context, Key};
+use serde_json::Value;
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    io::BufRead,
+    time::{Duration, SystemTime},
+};
+
+/// A compilation unit, which cargo finished.
+struct Unit {
+    package_id: String,
+    target: Value,
+    mode: String,
+    duration: f64,
+    rmeta_time: Option<f64>,
+    end_time: SystemTime,
+}
+
+/// Imports the `timing-info` messages of `cargo build --timings=json`. Cargo writes them when a
+/// unit finishes, but they only contain the duration. Without start time, the time a message is
+/// read is used as end time, which is only accurate when cargo's output is piped into tracebuild.
+/// With start time, e.g. for files, units are laid out one after another from it. Units are
+/// reported as soon as their message is read. Features are taken from the `compiler-artifact`
+/// messages in the same stream, which cargo writes before the unit finishes.
+pub(crate) fn import(
+    tracer: &BoxedTracer,
+    parent: &Context,
+    start_time: Option<SystemTime>,
+    input: impl BufRead,
+) -> Result<usize, ImportError> {
+    let mut next_start_time = start_time;
+    let mut count = 0;
+    let mut recorder = Recorder::new();
+    let mut features = HashMap::<(String, String), Vec<String>>::new();
+    for (index, line) in input.lines().enumerate() {
+        let line = line?;
+        let read_time = SystemTime::now();
+        // Cargo only writes JSON messages to stdout, but other tools in a pipeline may not.
+        if !line.starts_with('{') {
+            continue;
+        }
+
+        let message: Value =
+            serde_json::from_str(&line).map_err(|err| ImportError::InvalidLine {
+                line: index + 1,
+                message: err.to_string(),
+            })?;
+        let package_id = message["package_id"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string();
+        match message["reason"].as_str() {
+            Some("timing-info") => {
+                let duration = message["duration"].as_f64().unwrap_or_default();
+                let end_time = match &mut next_start_time {
+                    Some(time) => {
+                        *time += Duration::from_secs_f64(duration.max(0.0));
+                        *time
+                    }
+                    None => read_time,
+                };
+                let unit = Unit {
+                    package_id,
+                    target: message["target"].clone(),
+                    mode: message["mode"].as_str().unwrap_or("build").to_string(),
+                    duration,
+                    rmeta_time: message["rmeta_time"].as_f64(),
+                    end_time,
+                };
+                unit_span(unit, &features).record(tracer, &mut recorder, parent)?;
+                count += 1;
+            }
+            Some("compiler-artifact") => {
+                let target_name = message["target"]["name"].as_str().unwrap_or_default();
+                features.insert(
+                    (package_id, target_name.to_string()),
+                    message["features"]
+                        .as_array()
+                        .map(|features| {
+                            features
+                                .iter()
+                                .filter_map(|feature| feature.as_str().map(String::from))
+                                .collect()
+                        })
+                        .unwrap_or_default(),
+                );
+            }
+            _ => {}
+        }
+    }
+    recorder.finish()?;
+    Ok(count)
+}
+
+fn unit_span(unit: Unit, features: &HashMap<(String, String), Vec<String>>) -> ImportedSpan {
+    let (package, version) = parse_package_id(&unit.package_id);
+    let target_name = unit.target["name"].as_str().unwrap_or_default().to_string();
+    let target_kind = unit.target["kind"]
+        .as_array()
+        .map(|kinds| {
+            kinds
+                .iter()
+                .filter_map(|kind| kind.as_str())
+                .collect::<Vec<_>>()
+                .join(",")
+        })
+        .unwrap_or_default();
+
+    let mut name = format!("{} v{}", package, version);
+    if !matches!(target_kind.as_str(), "lib" | "rlib" | "proc-macro") {
+        name.push_str(&format!(" {} \"{}\"", target_kind, target_name));
+    }
+    if unit.mode != "build" {
+        name.push_str(&format!(" ({})", unit.mode));
+    }
+
+    let mut attributes = vec![
+        Key::new("tracebuild.cargo.package").string(package.to_string()),
+        Key::new("tracebuild.cargo.version").string(version.to_string()),
+        Key::new("tracebuild.cargo.target").string(target_name.clone()),
+        Key::new("tracebuild.cargo.target_kind").string(target_kind),
+        Key::new("tracebuild.cargo.mode").string(unit.mode),
+        Key::new("tracebuild.cargo.duration").f64(unit.duration),
+    ];
+    if let Some(rmeta_time) = unit.rmeta_time {
+        attributes.push(Key::new("tracebuild.cargo.rmeta_time").f64(rmeta_time));
+    }
+    if let Some(features) = features.get(&(unit.package_id.clone(), target_name)) {
+        attributes.push(
+            Key::new("tracebuild.cargo.features").array(
+                features
+                    .iter()
+                    .map(|feature| Cow::from(feature.clone()))
+                    .collect::<Vec<_>>(),
+            ),
+        );
+    }
+
+    let duration = Duration::from_secs_f64(unit.duration.max(0.0));
+    ImportedSpan {
+        name,
+        start_time: unit.end_time.checked_sub(duration).unwrap_or(unit.end_time),
+        end_time: unit.end_time,
+        attributes,
+        status: None,
+    }
+}
+
+/// Returns name and version of a package id. Newer cargo versions use package id specs like
+/// `registry+https://github.com/rust-lang/crates.io-index#serde@1.0.0` or
+/// `path+file:///path/to/foo#0.1.0`, where the name is omitted if it matches the last path
+/// segment of the URL. Older versions use `serde 1.0.0 (registry+https://...)`.
+fn parse_package_id(package_id: &str) -> (&str, &str) {
+    // Package id specs are URLs, which contain no spaces. The old format has a commit hash
+    // fragment in git source URLs, e.g. `foo 0.1.0 (git+https://...?branch=main#1234abcd)`.
+    if let Some((name, rest)) = package_id.split_once(' ') {
+        return (name, rest.split(' ').next().unwrap_or_default());
+    }
+    match package_id.rsplit_once('#') {
+        Some((url, fragment)) => match fragment.split_once('@') {
+            Some((name, version)) => (name, version),
+            None => {
+                // Git sources have the reference as query, e.g. `?branch=main`.
+                let path = url.split('?').next().unwrap_or(url);
+                (
+                    path.trim_end_matches('/')
+                        .rsplit('/')
+                        .next()
+                        .unwrap_or(path),
+                    fragment,
+                )
+            }
+        },
+        None => (package_id, ""),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_package_ids() {
+        for (package_id, name, version) in [
+            (
+                "registry+https://github.com/rust-lang/crates.io-index#serde@1.0.130",
+                "serde",
+                "1.0.130",
+            ),
+            ("path+file:///path/to/foo#0.1.0", "foo", "0.1.0"),
+            ("path+file:///path/to/foo/#0.1.0", "foo", "0.1.0"),
+            ("path+file:///path/to/dir#foo@0.1.0", "foo", "0.1.0"),
+            (
+                "git+https://github.com/rust-lang/cargo?branch=master#0.75.0",
+                "cargo",
+                "0.75.0",
+            ),
+            (
+                "git+https://github.com/rust-lang/cargo.git?rev=abc#cargo-util@0.2.6",
+                "cargo-util",
+                "0.2.6",
+            ),
+            (
+                "sparse+https://index.crates.io/#serde@1.0.130",
+                "serde",
+                "1.0.130",
+            ),
+            (
+                "serde 1.0.130 (registry+https://github.com/rust-lang/crates.io-index)",
+                "serde",
+                "1.0.130",
+            ),
+            ("foo 0.1.0 (path+file:///path/to/foo)", "foo", "0.1.0"),
+            (
+                "foo 0.1.0 (git+https://github.com/bar/foo?branch=main#1234abcd)",
+                "foo",
+                "0.1.0",
+            ),
+            ("", "", ""),
+        ] {
+            assert_eq!(
+                parse_package_id(package_id),
+                (name, version),
+                "{}",
+                package_id
+            );
+        }
+    }
+}
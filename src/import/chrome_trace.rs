@@ -0,0 +1,269 @@
+use super::{json_attribute, ImportError, ImportedSpan, Recorder};
+use opentelemetry::{global::BoxedTracer, Context, Key, KeyValue};
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    time::{Duration, SystemTime},
+};
+
+/// Process and thread id. Tools use numbers as well as strings.
+type Thread = (String, String);
+
+/// A complete event or a matching pair of begin and end events. Times are in microseconds.
+struct Slice {
+    thread: Thread,
+    name: String,
+    start: f64,
+    end: f64,
+    attributes: Vec<KeyValue>,
+}
+
+impl Slice {
+    fn new(event: &Value, start: f64, end: f64) -> Self {
+        let mut attributes = Vec::new();
+        if let Some(category) = event["cat"].as_str() {
+            attributes
+                .push(Key::new("tracebuild.chrome_trace.category").string(category.to_string()));
+        }
+        attributes.extend(json_attribute("tracebuild.chrome_trace.pid", &event["pid"]));
+        attributes.extend(json_attribute("tracebuild.chrome_trace.tid", &event["tid"]));
+        let mut slice = Self {
+            thread: thread(event),
+            name: event["name"].as_str().unwrap_or("unknown").to_string(),
+            start,
+            end,
+            attributes,
+        };
+        slice.add_args(event);
+        slice
+    }
+
+    fn add_args(&mut self, event: &Value) {
+        if let Some(args) = event["args"].as_object() {
+            self.attributes.extend(
+                args.iter()
+                    .filter_map(|(key, value)| json_attribute(key.clone(), value)),
+            );
+        }
+    }
+}
+
+fn thread(event: &Value) -> Thread {
+    (event["pid"].to_string(), event["tid"].to_string())
+}
+
+/// Imports complete (X) and begin/end (B/E) events of a Chrome trace-event profile. Events are
+/// nested by time within each thread. Timestamps in profiles are relative to an unspecified
+/// clock, so the earliest event is aligned with the given start time.
+pub(crate) fn import(
+    tracer: &BoxedTracer,
+    parent: &Context,
+    start_time: SystemTime,
+    input: &str,
+) -> Result<usize, ImportError> {
+    let mut slices = parse(input)?;
+    let parents = nest(&mut slices);
+    let trace_start = slices
+        .iter()
+        .map(|slice| slice.start)
+        .fold(f64::INFINITY, f64::min);
+    let to_system_time =
+        |ts: f64| start_time + Duration::from_secs_f64((ts - trace_start).max(0.0) / 1_000_000.0);
+
+    let count = slices.len();
+    let mut recorder = Recorder::new();
+    let mut contexts = Vec::<Context>::with_capacity(count);
+    for (slice, slice_parent) in slices.iter().zip(parents) {
+        let span_parent = slice_parent.map_or(parent, |index| &contexts[index]);
+        let cx = ImportedSpan {
+            name: slice.name.clone(),
+            start_time: to_system_time(slice.start),
+            end_time: to_system_time(slice.end),
+            attributes: slice.attributes.clone(),
+            status: None,
+        }
+        .record(tracer, &mut recorder, span_parent)?;
+        contexts.push(cx);
+    }
+    recorder.finish()?;
+    Ok(count)
+}
+
+/// Sorts the slices by thread and start time and returns the index of each slice's parent. The
+/// parent is the innermost slice of the same thread, which encloses the slice. Slices, which only
+/// overlap, are not nested.
+fn nest(slices: &mut [Slice]) -> Vec<Option<usize>> {
+    slices.sort_by(|a, b| {
+        a.thread
+            .cmp(&b.thread)
+            .then(a.start.total_cmp(&b.start))
+            .then(b.end.total_cmp(&a.end))
+    });
+    let mut parents = Vec::with_capacity(slices.len());
+    let mut stack = Vec::<usize>::new();
+    for (index, slice) in slices.iter().enumerate() {
+        while let Some(&top) = stack.last() {
+            if slices[top].thread == slice.thread && slices[top].end >= slice.end {
+                break;
+            }
+            stack.pop();
+        }
+        parents.push(stack.last().copied());
+        stack.push(index);
+    }
+    parents
+}
+
+/// Parses the events, which are either a JSON array or in the `traceEvents` field of a JSON
+/// object.
+fn parse(input: &str) -> Result<Vec<Slice>, ImportError> {
+    let value: Value = serde_json::from_str(input)?;
+    let events = match &value {
+        Value::Array(events) => events,
+        Value::Object(object) => match object.get("traceEvents") {
+            Some(Value::Array(events)) => events,
+            _ => return Err(ImportError::Invalid("Missing traceEvents array".into())),
+        },
+        _ => {
+            return Err(ImportError::Invalid(
+                "Expected an array or an object with traceEvents".into(),
+            ))
+        }
+    };
+
+    let mut slices = Vec::new();
+    let mut open = HashMap::<Thread, Vec<Slice>>::new();
+    let mut thread_names = HashMap::<Thread, String>::new();
+    let mut trace_end = 0.0f64;
+    for (index, event) in events.iter().enumerate() {
+        let phase = match event["ph"].as_str() {
+            Some(phase @ ("X" | "B" | "E")) => phase,
+            Some("M") if event["name"] == "thread_name" => {
+                if let Some(name) = event["args"]["name"].as_str() {
+                    thread_names.insert(thread(event), name.to_string());
+                }
+                continue;
+            }
+            _ => continue,
+        };
+        let ts = event["ts"]
+            .as_f64()
+            .ok_or_else(|| ImportError::Invalid(format!("Event {} has no timestamp", index)))?;
+        trace_end = trace_end.max(ts);
+        match phase {
+            "X" => {
+                let end = ts + event["dur"].as_f64().unwrap_or_default();
+                trace_end = trace_end.max(end);
+                slices.push(Slice::new(event, ts, end));
+            }
+            "B" => open
+                .entry(thread(event))
+                .or_default()
+                .push(Slice::new(event, ts, ts)),
+            _ => {
+                // End events close the last begin event of the same thread and can add args.
+                if let Some(mut slice) = open.get_mut(&thread(event)).and_then(Vec::pop) {
+                    slice.end = ts;
+                    slice.add_args(event);
+                    slices.push(slice);
+                }
+            }
+        }
+    }
+
+    // Begin events without end event last until the end of the trace.
+    for slice in open.into_values().flatten() {
+        slices.push(Slice {
+            end: trace_end,
+            ..slice
+        });
+    }
+
+    for slice in &mut slices {
+        if let Some(name) = thread_names.get(&slice.thread) {
+            slice
+                .attributes
+                .push(Key::new("tracebuild.chrome_trace.thread_name").string(name.clone()));
+        }
+    }
+    Ok(slices)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns the names of the slices and their parents, sorted by name.
+    fn nested(events: &Value) -> Vec<(String, Option<String>)> {
+        let mut slices = parse(&events.to_string()).unwrap();
+        let parents = nest(&mut slices);
+        let mut nested = parents
+            .iter()
+            .zip(&slices)
+            .map(|(parent, slice)| {
+                (
+                    slice.name.clone(),
+                    parent.map(|parent| slices[parent].name.clone()),
+                )
+            })
+            .collect::<Vec<_>>();
+        nested.sort();
+        nested
+    }
+
+    #[test]
+    fn nests_slices_by_time() {
+        for (events, expected) in [
+            // Enclosed slices are children, also with the same start or end.
+            (
+                serde_json::json!([
+                    {"ph": "X", "name": "a", "ts": 0, "dur": 10, "pid": 1, "tid": 1},
+                    {"ph": "X", "name": "b", "ts": 0, "dur": 5, "pid": 1, "tid": 1},
+                    {"ph": "X", "name": "c", "ts": 5, "dur": 5, "pid": 1, "tid": 1},
+                    {"ph": "X", "name": "d", "ts": 6, "dur": 1, "pid": 1, "tid": 1},
+                ]),
+                vec![
+                    ("a", None),
+                    ("b", Some("a")),
+                    ("c", Some("a")),
+                    ("d", Some("c")),
+                ],
+            ),
+            // Overlapping slices are siblings.
+            (
+                serde_json::json!([
+                    {"ph": "X", "name": "a", "ts": 0, "dur": 10, "pid": 1, "tid": 1},
+                    {"ph": "X", "name": "b", "ts": 5, "dur": 10, "pid": 1, "tid": 1},
+                    {"ph": "X", "name": "c", "ts": 6, "dur": 2, "pid": 1, "tid": 1},
+                    {"ph": "X", "name": "d", "ts": 12, "dur": 1, "pid": 1, "tid": 1},
+                ]),
+                vec![("a", None), ("b", None), ("c", Some("b")), ("d", Some("b"))],
+            ),
+            // Slices of other threads are never parents.
+            (
+                serde_json::json!([
+                    {"ph": "X", "name": "a", "ts": 0, "dur": 10, "pid": 1, "tid": 1},
+                    {"ph": "X", "name": "b", "ts": 2, "dur": 2, "pid": 1, "tid": "2"},
+                    {"ph": "X", "name": "c", "ts": 3, "dur": 1, "pid": 1, "tid": 1},
+                ]),
+                vec![("a", None), ("b", None), ("c", Some("a"))],
+            ),
+            // Begin and end events are matched per thread, unmatched begins last until the end.
+            (
+                serde_json::json!({"traceEvents": [
+                    {"ph": "B", "name": "a", "ts": 0, "pid": 1, "tid": 1},
+                    {"ph": "B", "name": "b", "ts": 1, "pid": 1, "tid": 1},
+                    {"ph": "E", "ts": 3, "pid": 1, "tid": 1},
+                    {"ph": "X", "name": "c", "ts": 4, "dur": 6, "pid": 1, "tid": 1},
+                ]}),
+                vec![("a", None), ("b", Some("a")), ("c", Some("a"))],
+            ),
+        ] {
+            let expected = expected
+                .into_iter()
+                .map(|(name, parent)| (name.to_string(), parent.map(String::from)))
+                .collect::<Vec<_>>();
+            assert_eq!(nested(&events), expected, "{}", events);
+        }
+    }
+}
@@ -0,0 +1,202 @@
+use super::{json_attribute, parse_timestamp, ImportError, ImportedSpan, Recorder};
+use crate::{id::BuildId, status::Status};
+use opentelemetry::{
+    global::BoxedTracer,
+    trace::{Span as _, SpanKind, StatusCode, TraceContextExt as _, Tracer as _},
+    Context, Key, KeyValue,
+};
+use serde_json::Value;
+use std::{borrow::Cow, time::SystemTime};
+
+/// Imports a workflow run and its jobs as returned by the GitHub REST API
+/// (`GET /repos/{owner}/{repo}/actions/runs/{run_id}` and `.../runs/{run_id}/jobs`). Reports a
+/// build span with a span for every job of the run attempt and a span for every step of the jobs.
+/// Without build id, the id is derived from the run id and attempt like `tracebuild id --from-ci`
+/// does, so importing the same run again results in the same trace.
+pub(crate) fn import(
+    tracer: &BoxedTracer,
+    build: Option<BuildId>,
+    run_input: &str,
+    jobs_input: &str,
+) -> Result<usize, ImportError> {
+    let run: Value = serde_json::from_str(run_input)?;
+    let jobs: Value = serde_json::from_str(jobs_input)?;
+    let run_id = run["id"]
+        .as_u64()
+        .ok_or_else(|| ImportError::Invalid("Expected a workflow run with an id".into()))?;
+    let run_attempt = run["run_attempt"].as_u64().unwrap_or(1);
+    // The jobs endpoint returns an object, but pages combined with e.g. jq are arrays.
+    let jobs = match &jobs {
+        Value::Array(jobs) => jobs,
+        Value::Object(object) => match object.get("jobs") {
+            Some(Value::Array(jobs)) => jobs,
+            _ => return Err(ImportError::Invalid("Missing jobs array".into())),
+        },
+        _ => {
+            return Err(ImportError::Invalid(
+                "Expected an array or an object with jobs".into(),
+            ))
+        }
+    };
+    let jobs = jobs
+        .iter()
+        .filter(|job| job["run_id"].as_u64().unwrap_or(run_id) == run_id)
+        .filter(|job| job["run_attempt"].as_u64().unwrap_or(run_attempt) == run_attempt)
+        .collect::<Vec<_>>();
+
+    let start_time = timestamp(&run["run_started_at"])
+        .or_else(|| timestamp(&run["created_at"]))
+        .ok_or_else(|| ImportError::Invalid("Workflow run has no start time".into()))?;
+    // Runs have no completion time, but their last job does.
+    let end_time = jobs
+        .iter()
+        .filter_map(|job| timestamp(&job["completed_at"]))
+        .max()
+        .or_else(|| timestamp(&run["updated_at"]))
+        .unwrap_or(start_time)
+        .max(start_time);
+
+    let build = build.unwrap_or_else(|| {
+        BuildId::from_seed(&format!("github_actions/{}/{}", run_id, run_attempt))
+    });
+    let span_name: Cow<'static, str> = match run["name"].as_str() {
+        Some(name) => format!("build - {}", name).into(),
+        None => "build".into(),
+    };
+    let mut attributes = vec![Key::new("tracebuild.ci.provider").string("github_actions")];
+    attributes.extend(json_attribute("tracebuild.ci.run_url", &run["html_url"]));
+    attributes.extend(json_attribute("tracebuild.ci.event", &run["event"]));
+    attributes.extend(json_attribute(
+        "tracebuild.build.branch",
+        &run["head_branch"],
+    ));
+    attributes.extend(json_attribute("tracebuild.build.commit", &run["head_sha"]));
+    attributes.push(Key::new("tracebuild.github.run_id").i64(run_id as i64));
+    attributes.extend(json_attribute(
+        "tracebuild.github.run_number",
+        &run["run_number"],
+    ));
+    attributes.push(Key::new("tracebuild.github.run_attempt").i64(run_attempt as i64));
+    attributes.extend(conclusion_attribute(&run["conclusion"]));
+    let span = tracer
+        .span_builder(&span_name)
+        .with_start_time(start_time)
+        .with_trace_id(build.trace_id())
+        .with_span_id(build.span_id())
+        .with_kind(SpanKind::Internal)
+        .with_attributes(attributes)
+        .start(tracer);
+    if let Some((code, message)) = status(&run["conclusion"]) {
+        span.set_status(code, message);
+    }
+    let cx = &Context::current().with_span(span);
+
+    let mut recorder = Recorder::new();
+    let mut count = 1;
+    for job in jobs {
+        count += import_job(tracer, &mut recorder, cx, job)?;
+    }
+    recorder.end(cx, end_time)?;
+    recorder.finish()?;
+    Ok(count)
+}
+
+/// Reports the job and its steps. Returns the number of reported spans. Jobs, which never
+/// started, e.g. because they were skipped, are not reported.
+fn import_job(
+    tracer: &BoxedTracer,
+    recorder: &mut Recorder,
+    parent: &Context,
+    job: &Value,
+) -> Result<usize, ImportError> {
+    let start_time = match timestamp(&job["started_at"]) {
+        Some(start_time) => start_time,
+        None => return Ok(0),
+    };
+    let mut attributes = Vec::new();
+    attributes.extend(json_attribute("tracebuild.github.job_id", &job["id"]));
+    attributes.extend(json_attribute(
+        "tracebuild.github.job_url",
+        &job["html_url"],
+    ));
+    attributes.extend(json_attribute(
+        "tracebuild.github.runner_name",
+        &job["runner_name"],
+    ));
+    if let Some(labels) = job["labels"].as_array() {
+        attributes.push(
+            Key::new("tracebuild.github.runner_labels").array(
+                labels
+                    .iter()
+                    .filter_map(|label| label.as_str().map(|label| Cow::from(label.to_string())))
+                    .collect::<Vec<_>>(),
+            ),
+        );
+    }
+    attributes.extend(conclusion_attribute(&job["conclusion"]));
+    let cx = &ImportedSpan {
+        name: format!("job - {}", job["name"].as_str().unwrap_or("unknown")),
+        start_time,
+        end_time: end_time(&job["completed_at"], start_time),
+        attributes,
+        status: status(&job["conclusion"]),
+    }
+    .record(tracer, recorder, parent)?;
+
+    let mut count = 1;
+    for step in job["steps"].as_array().into_iter().flatten() {
+        let start_time = match timestamp(&step["started_at"]) {
+            Some(start_time) => start_time,
+            None => continue,
+        };
+        let mut attributes = Vec::new();
+        attributes.extend(json_attribute(
+            "tracebuild.github.step_number",
+            &step["number"],
+        ));
+        attributes.extend(conclusion_attribute(&step["conclusion"]));
+        ImportedSpan {
+            name: format!("step - {}", step["name"].as_str().unwrap_or("unknown")),
+            start_time,
+            end_time: end_time(&step["completed_at"], start_time),
+            attributes,
+            status: status(&step["conclusion"]),
+        }
+        .record(tracer, recorder, cx)?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// Maps a conclusion onto the status of the span. Conclusions like skipped or neutral leave the
+/// status unset.
+fn status(conclusion: &Value) -> Option<(StatusCode, String)> {
+    let conclusion = conclusion.as_str()?;
+    let status = match conclusion {
+        "success" => Status::Success,
+        "failure" | "timed_out" | "cancelled" | "startup_failure" => Status::Failure,
+        _ => return None,
+    };
+    let message = match status {
+        Status::Success => String::new(),
+        Status::Failure => conclusion.to_string(),
+    };
+    Some(((&status).into(), message))
+}
+
+fn conclusion_attribute(conclusion: &Value) -> Option<KeyValue> {
+    json_attribute("tracebuild.github.conclusion", conclusion)
+}
+
+/// End time of a job or step. Jobs and steps in progress end now.
+fn end_time(completed_at: &Value, start_time: SystemTime) -> SystemTime {
+    timestamp(completed_at)
+        .unwrap_or_else(SystemTime::now)
+        .max(start_time)
+}
+
+/// Parses a timestamp of the API. The API returns UTC times for runs and jobs, but local times
+/// with offset, e.g. `2020-01-20T09:42:40.000-08:00`, for steps.
+fn timestamp(value: &Value) -> Option<SystemTime> {
+    value.as_str().and_then(parse_timestamp)
+}
@@ -0,0 +1,417 @@
+use super::{parse_timestamp, ImportError, ImportedSpan, Recorder};
+use opentelemetry::{
+    global::BoxedTracer,
+    metrics::{Counter, Meter, ValueRecorder},
+    trace::{StatusCode, TraceContextExt as _},
+    Context, Key, KeyValue, Unit,
+};
+use roxmltree::Node;
+use std::time::{Duration, SystemTime};
+
+#[derive(Clone, Copy)]
+enum Outcome {
+    Passed,
+    Failed,
+    Error,
+    Skipped,
+}
+
+impl Outcome {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Outcome::Passed => "passed",
+            Outcome::Failed => "failed",
+            Outcome::Error => "error",
+            Outcome::Skipped => "skipped",
+        }
+    }
+}
+
+#[derive(Default)]
+struct Counts {
+    passed: u64,
+    failed: u64,
+    skipped: u64,
+}
+
+impl Counts {
+    fn add(&mut self, other: &Counts) {
+        self.passed += other.passed;
+        self.failed += other.failed;
+        self.skipped += other.skipped;
+    }
+}
+
+struct Metrics {
+    duration: Option<ValueRecorder<f64>>,
+    passed: Option<Counter<u64>>,
+    failed: Option<Counter<u64>>,
+    skipped: Option<Counter<u64>>,
+}
+
+impl Metrics {
+    fn new(meter: &Meter) -> Self {
+        fn report<T>(name: &str, instrument: opentelemetry::metrics::Result<T>) -> Option<T> {
+            instrument
+                .map_err(|err| eprintln!("Failed to record {}: {}", name, err))
+                .ok()
+        }
+
+        Self {
+            duration: report(
+                "tracebuild.test.duration",
+                meter
+                    .f64_value_recorder("tracebuild.test.duration")
+                    .with_unit(Unit::new("seconds"))
+                    .try_init(),
+            ),
+            passed: report(
+                "tracebuild.test.passed",
+                meter.u64_counter("tracebuild.test.passed").try_init(),
+            ),
+            failed: report(
+                "tracebuild.test.failed",
+                meter.u64_counter("tracebuild.test.failed").try_init(),
+            ),
+            skipped: report(
+                "tracebuild.test.skipped",
+                meter.u64_counter("tracebuild.test.skipped").try_init(),
+            ),
+        }
+    }
+}
+
+struct Importer<'a> {
+    tracer: &'a BoxedTracer,
+    recorder: Recorder,
+    metrics: Metrics,
+    /// Labels added to all metrics, e.g. the build name.
+    labels: Vec<KeyValue>,
+    span_count: usize,
+}
+
+/// Imports a JUnit XML report. Reports only contain durations, so test suites without timestamp
+/// start after the previous one, beginning at the given start time. Test cases are assumed to
+/// run one after another.
+pub(crate) fn import(
+    tracer: &BoxedTracer,
+    meter: &Meter,
+    parent: &Context,
+    start_time: Option<SystemTime>,
+    labels: Vec<KeyValue>,
+    input: &str,
+) -> Result<usize, ImportError> {
+    let document = roxmltree::Document::parse(input)?;
+    let root = document.root_element();
+    let suites = match root.tag_name().name() {
+        "testsuites" => root
+            .children()
+            .filter(|node| node.has_tag_name("testsuite"))
+            .collect(),
+        "testsuite" => vec![root],
+        name => {
+            return Err(ImportError::Invalid(format!(
+                "Unexpected root element <{}>. Expected <testsuites> or <testsuite>",
+                name
+            )))
+        }
+    };
+
+    let total_duration = suites.iter().map(|suite| duration(*suite)).sum();
+    let mut time = start_time.unwrap_or_else(|| {
+        SystemTime::now()
+            .checked_sub(total_duration)
+            .unwrap_or_else(SystemTime::now)
+    });
+    let mut importer = Importer {
+        tracer,
+        recorder: Recorder::new(),
+        metrics: Metrics::new(meter),
+        labels,
+        span_count: 0,
+    };
+    for suite in suites {
+        time = importer.suite(suite, parent, time)?.1;
+    }
+    importer.recorder.finish()?;
+    Ok(importer.span_count)
+}
+
+impl Importer<'_> {
+    /// Reports the suite and its test cases and nested suites. Returns the test counts and the
+    /// time the suite ended.
+    fn suite(
+        &mut self,
+        suite: Node,
+        parent: &Context,
+        time: SystemTime,
+    ) -> Result<(Counts, SystemTime), ImportError> {
+        let name = suite.attribute("name").unwrap_or("unknown");
+        let start_time = suite_start_time(suite, time);
+        let end_time = start_time + duration(suite);
+
+        let mut attributes = vec![Key::new("tracebuild.test.suite").string(name.to_string())];
+        if let Some(hostname) = suite.attribute("hostname") {
+            attributes.push(Key::new("tracebuild.test.hostname").string(hostname.to_string()));
+        }
+        // The status depends on the test cases, so the span ends after them.
+        let span = ImportedSpan {
+            name: format!("testsuite - {}", name),
+            start_time,
+            end_time,
+            attributes,
+            status: None,
+        };
+        let cx = &span.start(self.tracer, parent);
+        self.span_count += 1;
+
+        // Metrics only count the suite's own test cases, the span includes nested suites.
+        let mut own_counts = Counts::default();
+        let mut nested_counts = Counts::default();
+        let mut time = start_time;
+        for node in suite.children().filter(Node::is_element) {
+            if node.has_tag_name("testcase") {
+                let outcome = self.case(node, name, cx, time)?;
+                match outcome {
+                    Outcome::Passed => own_counts.passed += 1,
+                    Outcome::Failed | Outcome::Error => own_counts.failed += 1,
+                    Outcome::Skipped => own_counts.skipped += 1,
+                }
+                time += duration(node);
+            } else if node.has_tag_name("testsuite") {
+                let (suite_counts, suite_end_time) = self.suite(node, cx, time)?;
+                nested_counts.add(&suite_counts);
+                time = suite_end_time;
+            }
+        }
+
+        let mut labels = self.labels.clone();
+        labels.push(Key::new("suite").string(name.to_string()));
+        for (counter, count) in [
+            (&self.metrics.passed, own_counts.passed),
+            (&self.metrics.failed, own_counts.failed),
+            (&self.metrics.skipped, own_counts.skipped),
+        ] {
+            if let Some(counter) = counter {
+                counter.add(count, &labels);
+            }
+        }
+
+        let mut counts = own_counts;
+        counts.add(&nested_counts);
+        let span = cx.span();
+        span.set_attribute(Key::new("tracebuild.test.passed").i64(counts.passed as i64));
+        span.set_attribute(Key::new("tracebuild.test.failed").i64(counts.failed as i64));
+        span.set_attribute(Key::new("tracebuild.test.skipped").i64(counts.skipped as i64));
+        if counts.failed > 0 {
+            span.set_status(
+                StatusCode::Error,
+                format!(
+                    "{} of {} tests failed",
+                    counts.failed,
+                    counts.passed + counts.failed
+                ),
+            );
+        } else {
+            span.set_status(StatusCode::Ok, "".into());
+        }
+        self.recorder.end(cx, end_time)?;
+        Ok((counts, end_time.max(time)))
+    }
+
+    /// Reports the test case and returns its outcome.
+    fn case(
+        &mut self,
+        case: Node,
+        suite: &str,
+        parent: &Context,
+        start_time: SystemTime,
+    ) -> Result<Outcome, ImportError> {
+        let name = case.attribute("name").unwrap_or("unknown");
+        let mut attributes = vec![
+            Key::new("tracebuild.test.suite").string(suite.to_string()),
+            Key::new("tracebuild.test.name").string(name.to_string()),
+        ];
+        for (attribute, key) in [
+            ("classname", "tracebuild.test.classname"),
+            ("file", "tracebuild.test.file"),
+            ("line", "tracebuild.test.line"),
+        ] {
+            if let Some(value) = case.attribute(attribute) {
+                attributes.push(Key::new(key).string(value.to_string()));
+            }
+        }
+
+        let result = case.children().find(|node| {
+            node.has_tag_name("failure")
+                || node.has_tag_name("error")
+                || node.has_tag_name("skipped")
+        });
+        let outcome = match result.map(|node| node.tag_name().name()) {
+            Some("failure") => Outcome::Failed,
+            Some("error") => Outcome::Error,
+            Some("skipped") => Outcome::Skipped,
+            _ => Outcome::Passed,
+        };
+        attributes.push(Key::new("tracebuild.test.outcome").string(outcome.as_str()));
+        let message = result.and_then(|node| {
+            node.attribute("message")
+                .or_else(|| node.text().map(str::trim).filter(|text| !text.is_empty()))
+        });
+        if let Some(message) = message {
+            attributes.push(Key::new("tracebuild.test.message").string(message.to_string()));
+        }
+        if let Some(kind) = result.and_then(|node| node.attribute("type")) {
+            attributes.push(Key::new("tracebuild.test.failure_type").string(kind.to_string()));
+        }
+        let status = match outcome {
+            Outcome::Passed => Some((StatusCode::Ok, String::new())),
+            Outcome::Failed | Outcome::Error => Some((
+                StatusCode::Error,
+                message.unwrap_or("Test failed").to_string(),
+            )),
+            Outcome::Skipped => None,
+        };
+
+        let duration = duration(case);
+        ImportedSpan {
+            name: format!("testcase - {}", name),
+            start_time,
+            end_time: start_time + duration,
+            attributes,
+            status,
+        }
+        .record(self.tracer, &mut self.recorder, parent)?;
+        self.span_count += 1;
+
+        if let Some(recorder) = &self.metrics.duration {
+            let mut labels = self.labels.clone();
+            labels.extend(vec![
+                Key::new("suite").string(suite.to_string()),
+                Key::new("outcome").string(outcome.as_str()),
+            ]);
+            recorder.record(duration.as_secs_f64(), &labels);
+        }
+        Ok(outcome)
+    }
+}
+
+/// Start time from the timestamp attribute, or the given time if the attribute is missing or
+/// invalid.
+fn suite_start_time(suite: Node, time: SystemTime) -> SystemTime {
+    suite
+        .attribute("timestamp")
+        .and_then(parse_timestamp)
+        .unwrap_or(time)
+}
+
+/// Duration from the time attribute, or the sum of the children if the attribute is missing.
+fn duration(node: Node) -> Duration {
+    node.attribute("time")
+        .and_then(parse_secs)
+        .filter(|secs| secs.is_finite() && *secs >= 0.0)
+        .map(Duration::from_secs_f64)
+        .unwrap_or_else(|| {
+            node.children()
+                .filter(|child| child.has_tag_name("testcase") || child.has_tag_name("testsuite"))
+                .map(duration)
+                .sum()
+        })
+}
+
+/// Parses seconds formatted with the reporter's locale, e.g. `1,234.5` or `1,5`. Commas are
+/// thousands separators if there is a decimal point or more than one comma, otherwise a lone comma
+/// is the decimal point.
+fn parse_secs(time: &str) -> Option<f64> {
+    let time = time.trim();
+    let time = if !time.contains(',') {
+        time.to_string()
+    } else if time.contains('.') || time.matches(',').count() > 1 {
+        let integer = time.split('.').next().unwrap_or_default();
+        let grouped = integer
+            .split(',')
+            .skip(1)
+            .all(|group| group.len() == 3 && group.bytes().all(|byte| byte.is_ascii_digit()));
+        if !grouped {
+            return None;
+        }
+        time.replace(',', "")
+    } else {
+        time.replace(',', ".")
+    };
+    time.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_durations() {
+        for (time, secs) in [
+            (Some("1.5"), 1.5),
+            (Some(" 2 "), 2.0),
+            (Some("1,5"), 1.5),
+            (Some("0,001"), 0.001),
+            (Some("1,234.5"), 1234.5),
+            (Some("1,234,567"), 1234567.0),
+            // Invalid and missing times fall back to the sum of the children.
+            (Some("1,5.0"), 3.0),
+            (Some("12,34.5"), 3.0),
+            (Some("-1"), 3.0),
+            (Some("NaN"), 3.0),
+            (Some(""), 3.0),
+            (None, 3.0),
+        ] {
+            let time = time.map_or(String::new(), |time| format!(" time=\"{}\"", time));
+            let xml = format!(
+                "<testsuite{}><testcase time=\"1\"/><testsuite><testcase time=\"2\"/></testsuite></testsuite>",
+                time
+            );
+            let document = roxmltree::Document::parse(&xml).unwrap();
+            assert_eq!(
+                duration(document.root_element()),
+                Duration::from_secs_f64(secs),
+                "{}",
+                xml
+            );
+        }
+    }
+
+    #[test]
+    fn parses_suite_timestamps() {
+        let time = SystemTime::UNIX_EPOCH;
+        // 2024-01-01T08:00:00Z
+        let utc = SystemTime::UNIX_EPOCH + Duration::from_secs(1704096000);
+        for (timestamp, expected) in [
+            (Some("2024-01-01T08:00:00"), utc),
+            (Some("2024-01-01T08:00:00.000Z"), utc),
+            (Some("2024-01-01T10:00:00+02:00"), utc),
+            (
+                Some("2024-01-01T10:00:00.123456+02:00"),
+                utc + Duration::from_micros(123456),
+            ),
+            (Some("2024-01-01T03:00:00-05:00"), utc),
+            (Some("yesterday"), time),
+            (None, time),
+        ] {
+            let timestamp = timestamp.map_or(String::new(), |timestamp| {
+                format!(" timestamp=\"{}\"", timestamp)
+            });
+            let xml = format!("<testsuite{}/>", timestamp);
+            let document = roxmltree::Document::parse(&xml).unwrap();
+            assert_eq!(
+                suite_start_time(document.root_element(), time),
+                expected,
+                "{}",
+                xml
+            );
+        }
+    }
+
+    #[test]
+    fn missing_case_times_are_zero() {
+        let document = roxmltree::Document::parse("<testcase name=\"test\"/>").unwrap();
+        assert_eq!(duration(document.root_element()), Duration::ZERO);
+    }
+}
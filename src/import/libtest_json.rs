@@ -0,0 +1,309 @@
+use super::{ImportError, ImportedSpan, Recorder};
+use opentelemetry::{
+    global::BoxedTracer,
+    trace::{StatusCode, TraceContextExt as _},
+    Context, Key,
+};
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    io::BufRead,
+    time::{Duration, SystemTime},
+};
+
+/// Captured output is attached to failed tests up to this size. The end of the output is kept,
+/// because it contains the panic message.
+const MAX_STDOUT_BYTES: usize = 8192;
+
+/// Imports the test events of libtest's JSON output, as written by
+/// `cargo test -- -Z unstable-options --format json --report-time` or nextest's libtest-json
+/// message format. Tests are timed when their events are read, so the output should be piped into
+/// tracebuild. The reported exec time takes precedence, if available.
+pub(crate) fn import(
+    tracer: &BoxedTracer,
+    parent: &Context,
+    input: impl BufRead,
+) -> Result<usize, ImportError> {
+    let mut tests = Tests::default();
+    let mut count = 0;
+    let mut recorder = Recorder::new();
+    for (index, line) in input.lines().enumerate() {
+        let line = line?;
+        let time = SystemTime::now();
+        // Tests run with --nocapture write their output in between.
+        if !line.starts_with('{') {
+            continue;
+        }
+
+        let event: Value = serde_json::from_str(&line).map_err(|err| ImportError::InvalidLine {
+            line: index + 1,
+            message: err.to_string(),
+        })?;
+        let test = match tests.event(&event, time) {
+            Some(test) => test,
+            None => continue,
+        };
+        let cx = test.span.start(tracer, parent);
+        if let Some(stdout) = test.stdout {
+            cx.span().add_event_with_timestamp(
+                "stdout".into(),
+                time,
+                vec![Key::new("tracebuild.test.stdout").string(stdout)],
+            );
+        }
+        recorder.end(&cx, time)?;
+        count += 1;
+    }
+    recorder.finish()?;
+    Ok(count)
+}
+
+/// A test, which finished.
+struct FinishedTest {
+    span: ImportedSpan,
+    /// End of the captured output of the test.
+    stdout: Option<String>,
+}
+
+/// Pairs the started events of tests with their results.
+#[derive(Default)]
+struct Tests {
+    started: HashMap<String, SystemTime>,
+}
+
+impl Tests {
+    /// Handles an event read at the given time. Returns the test, if the event is a test result.
+    fn event(&mut self, event: &Value, time: SystemTime) -> Option<FinishedTest> {
+        if event["type"] != "test" {
+            return None;
+        }
+        let name = event["name"].as_str().unwrap_or("unknown");
+        let outcome = match event["event"].as_str() {
+            Some("started") => {
+                self.started.insert(name.to_string(), time);
+                return None;
+            }
+            Some("ok") => "passed",
+            Some("failed") => "failed",
+            Some("ignored") => "skipped",
+            // E.g. "timeout", which only warns about long running tests.
+            _ => return None,
+        };
+
+        let exec_time = event["exec_time"]
+            .as_f64()
+            .filter(|secs| secs.is_finite() && *secs >= 0.0);
+        let start_time = self.started.remove(name).unwrap_or(time);
+        let start_time = exec_time
+            .and_then(|secs| time.checked_sub(Duration::from_secs_f64(secs)))
+            .unwrap_or(start_time);
+
+        let mut attributes = vec![Key::new("tracebuild.test.name").string(name.to_string())];
+        // Nextest prefixes test names with the binary, e.g. `crate::bin$module::test`.
+        if let Some((binary, _)) = name.split_once('$') {
+            attributes.push(Key::new("tracebuild.test.binary").string(binary.to_string()));
+        }
+        attributes.push(Key::new("tracebuild.test.outcome").string(outcome));
+        if let Some(exec_time) = exec_time {
+            attributes.push(Key::new("tracebuild.test.exec_time").f64(exec_time));
+        }
+        let message = event["message"].as_str();
+        if let Some(message) = message {
+            attributes.push(Key::new("tracebuild.test.message").string(message.to_string()));
+        }
+        let status = match outcome {
+            "passed" => Some((StatusCode::Ok, String::new())),
+            "failed" => Some((
+                StatusCode::Error,
+                message.unwrap_or("Test failed").to_string(),
+            )),
+            _ => None,
+        };
+
+        Some(FinishedTest {
+            span: ImportedSpan {
+                name: format!("test - {}", name),
+                start_time,
+                end_time: time,
+                attributes,
+                status,
+            },
+            stdout: event["stdout"]
+                .as_str()
+                .filter(|stdout| !stdout.is_empty())
+                .map(|stdout| tail(stdout).to_string()),
+        })
+    }
+}
+
+/// Returns at most the last `MAX_STDOUT_BYTES` of the output, respecting char boundaries.
+fn tail(output: &str) -> &str {
+    let mut start = output.len().saturating_sub(MAX_STDOUT_BYTES);
+    while !output.is_char_boundary(start) {
+        start += 1;
+    }
+    &output[start..]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry::Value as AttributeValue;
+    use serde_json::json;
+
+    fn attribute(span: &ImportedSpan, key: &str) -> Option<AttributeValue> {
+        span.attributes
+            .iter()
+            .find(|kv| kv.key.as_str() == key)
+            .map(|kv| kv.value.clone())
+    }
+
+    #[test]
+    fn pairs_events() {
+        let time = |secs| SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
+        for (events, name, start, end, outcome, status) in [
+            (
+                vec![
+                    json!({"type": "test", "event": "started", "name": "a"}),
+                    json!({"type": "test", "event": "ok", "name": "a"}),
+                ],
+                "a",
+                0,
+                1,
+                "passed",
+                Some(StatusCode::Ok),
+            ),
+            // The exec time takes precedence over the started event.
+            (
+                vec![
+                    json!({"type": "test", "event": "started", "name": "a"}),
+                    json!({"type": "test", "event": "failed", "name": "a", "exec_time": 0.0}),
+                ],
+                "a",
+                1,
+                1,
+                "failed",
+                Some(StatusCode::Error),
+            ),
+            // Results of other tests don't end a started test.
+            (
+                vec![
+                    json!({"type": "test", "event": "started", "name": "a"}),
+                    json!({"type": "test", "event": "started", "name": "b"}),
+                    json!({"type": "test", "event": "ignored", "name": "b"}),
+                ],
+                "b",
+                1,
+                2,
+                "skipped",
+                None,
+            ),
+            // Results without started event and exec time take no time.
+            (
+                vec![
+                    json!({"type": "suite", "event": "started", "test_count": 1}),
+                    json!({"type": "test", "event": "timeout", "name": "a"}),
+                    json!({"type": "test", "event": "ok", "name": "a"}),
+                ],
+                "a",
+                2,
+                2,
+                "passed",
+                Some(StatusCode::Ok),
+            ),
+            (
+                vec![
+                    json!({"type": "test", "event": "started", "name": "a"}),
+                    json!({"type": "test", "event": "ok", "name": "a", "exec_time": -1.0}),
+                ],
+                "a",
+                0,
+                1,
+                "passed",
+                Some(StatusCode::Ok),
+            ),
+        ] {
+            let mut tests = Tests::default();
+            let mut finished = events
+                .iter()
+                .enumerate()
+                .filter_map(|(index, event)| tests.event(event, time(index as u64)))
+                .collect::<Vec<_>>();
+            assert_eq!(finished.len(), 1, "{:?}", events);
+            let span = finished.remove(0).span;
+            assert_eq!(span.name, format!("test - {}", name), "{:?}", events);
+            assert_eq!(span.start_time, time(start), "{:?}", events);
+            assert_eq!(span.end_time, time(end), "{:?}", events);
+            assert_eq!(
+                attribute(&span, "tracebuild.test.outcome"),
+                Some(outcome.into()),
+                "{:?}",
+                events
+            );
+            assert_eq!(span.status.map(|(code, _)| code), status, "{:?}", events);
+        }
+    }
+
+    #[test]
+    fn records_failures() {
+        let mut tests = Tests::default();
+        let test = tests
+            .event(
+                &json!({
+                    "type": "test",
+                    "event": "failed",
+                    "name": "crate::bin$module::test",
+                    "exec_time": 0.5,
+                    "stdout": "panicked",
+                    "message": "assertion failed",
+                }),
+                SystemTime::UNIX_EPOCH + Duration::from_secs(1),
+            )
+            .unwrap();
+        assert_eq!(
+            test.span.start_time,
+            SystemTime::UNIX_EPOCH + Duration::from_millis(500)
+        );
+        assert_eq!(
+            attribute(&test.span, "tracebuild.test.binary"),
+            Some("crate::bin".into())
+        );
+        assert_eq!(
+            attribute(&test.span, "tracebuild.test.message"),
+            Some("assertion failed".into())
+        );
+        assert_eq!(
+            test.span.status,
+            Some((StatusCode::Error, "assertion failed".to_string()))
+        );
+        assert_eq!(test.stdout.as_deref(), Some("panicked"));
+
+        let test = tests
+            .event(
+                &json!({"type": "test", "event": "failed", "name": "a", "stdout": ""}),
+                SystemTime::UNIX_EPOCH,
+            )
+            .unwrap();
+        assert_eq!(attribute(&test.span, "tracebuild.test.binary"), None);
+        assert_eq!(
+            test.span.status,
+            Some((StatusCode::Error, "Test failed".to_string()))
+        );
+        assert_eq!(test.stdout, None);
+    }
+
+    #[test]
+    fn keeps_end_of_output() {
+        let long = "x".repeat(MAX_STDOUT_BYTES);
+        for (output, expected) in [
+            ("short", "short".to_string()),
+            (&format!("a{}", long), long.clone()),
+            // The cut falls into the two bytes of ä, which is dropped.
+            (&format!("ä{}", long), long.clone()),
+            (&format!("ä{}", &long[1..]), long[1..].to_string()),
+            (&format!("ä{}", &long[2..]), format!("ä{}", &long[2..])),
+        ] {
+            assert_eq!(tail(output), expected);
+        }
+    }
+}
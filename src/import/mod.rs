@@ -0,0 +1,233 @@
+pub(crate) mod cargo_timings;
+pub(crate) mod chrome_trace;
+pub(crate) mod github_actions;
+pub(crate) mod junit;
+pub(crate) mod libtest_json;
+pub(crate) mod ninja;
+
+use crate::pipeline;
+use opentelemetry::{
+    global::BoxedTracer,
+    trace::{Span as _, SpanKind, StatusCode, TraceContextExt as _, TraceError, Tracer as _},
+    Context, Key, KeyValue,
+};
+use serde_json::Value;
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, Read as _},
+    path::Path,
+    time::{Duration, SystemTime},
+};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub(crate) enum ImportError {
+    #[error("Failed to read input: {0}")]
+    Io(#[from] io::Error),
+    #[error("Invalid input at line {line}: {message}")]
+    InvalidLine { line: usize, message: String },
+    #[error("Invalid input: {0}")]
+    Invalid(String),
+    #[error("Invalid JSON: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("Invalid XML: {0}")]
+    Xml(#[from] roxmltree::Error),
+    #[error("Failed to export spans: {0}")]
+    Export(#[from] TraceError),
+}
+
+/// Reads the file at the given path, or stdin if the path is "-".
+pub(crate) fn read_input(path: &Path) -> Result<String, ImportError> {
+    if path == Path::new("-") {
+        let mut input = String::new();
+        io::stdin().read_to_string(&mut input)?;
+        Ok(input)
+    } else {
+        Ok(std::fs::read_to_string(path)?)
+    }
+}
+
+/// Opens the file at the given path, or stdin if the path is "-", for reading line by line.
+pub(crate) fn open_input(path: &Path) -> Result<Box<dyn BufRead>, ImportError> {
+    if path == Path::new("-") {
+        Ok(Box::new(BufReader::new(io::stdin())))
+    } else {
+        Ok(Box::new(BufReader::new(File::open(path)?)))
+    }
+}
+
+/// Returns whether the input at the given path, or stdin if the path is "-", is a file. Files
+/// are read at once, so imports can't time messages when they are read.
+pub(crate) fn is_file_input(path: &Path) -> Result<bool, ImportError> {
+    if path != Path::new("-") {
+        return Ok(true);
+    }
+    #[cfg(unix)]
+    {
+        use nix::sys::stat::{fstat, SFlag};
+
+        let stat = fstat(libc::STDIN_FILENO).map_err(crate::cmd::nix_to_io_error)?;
+        Ok(SFlag::from_bits_truncate(stat.st_mode) & SFlag::S_IFMT == SFlag::S_IFREG)
+    }
+    #[cfg(not(unix))]
+    Ok(false)
+}
+
+/// Parses an RFC 3339 timestamp. Unlike humantime, this accepts UTC offsets like `+02:00`, which
+/// e.g. pytest and the GitHub API write. Timestamps without offset are taken as UTC.
+pub(crate) fn parse_timestamp(s: &str) -> Option<SystemTime> {
+    let (time, offset) = match s
+        .len()
+        .checked_sub(6)
+        .filter(|index| s.is_char_boundary(*index))
+        .map(|index| s.split_at(index))
+    {
+        Some((time, offset))
+            if time.contains('T') && (offset.starts_with('+') || offset.starts_with('-')) =>
+        {
+            (time, offset)
+        }
+        _ => return humantime::parse_rfc3339_weak(s).ok(),
+    };
+    let (hours, minutes) = offset[1..].split_once(':')?;
+    let offset_secs = hours.parse::<u64>().ok()? * 3600 + minutes.parse::<u64>().ok()? * 60;
+    let local = humantime::parse_rfc3339_weak(time).ok()?;
+    if offset.starts_with('+') {
+        local.checked_sub(Duration::from_secs(offset_secs))
+    } else {
+        local.checked_add(Duration::from_secs(offset_secs))
+    }
+}
+
+/// Converts a JSON value into an attribute. Objects and arrays are kept as JSON strings. Null
+/// values are skipped.
+pub(crate) fn json_attribute(key: impl Into<Key>, value: &Value) -> Option<KeyValue> {
+    let key = key.into();
+    match value {
+        Value::Null => None,
+        Value::Bool(value) => Some(key.bool(*value)),
+        Value::Number(number) => Some(match number.as_i64() {
+            Some(value) => key.i64(value),
+            None => key.f64(number.as_f64().unwrap_or_default()),
+        }),
+        Value::String(value) => Some(key.string(value.clone())),
+        Value::Array(_) | Value::Object(_) => Some(key.string(value.to_string())),
+    }
+}
+
+/// Ends imported spans. Imports end spans much faster than the batch span processor exports them,
+/// which drops spans once its queue (`OTEL_BSP_MAX_QUEUE_SIZE`) is full. So the trace pipeline is
+/// flushed whenever half of the queue is filled.
+pub(crate) struct Recorder {
+    unflushed: usize,
+    flush_interval: usize,
+    reported_errors: usize,
+}
+
+impl Recorder {
+    pub(crate) fn new() -> Self {
+        Self {
+            unflushed: 0,
+            flush_interval: (pipeline::max_queue_size() / 2).max(1),
+            reported_errors: pipeline::reported_errors(),
+        }
+    }
+
+    /// Ends the span of the context with the given end time.
+    pub(crate) fn end(&mut self, cx: &Context, end_time: SystemTime) -> Result<(), ImportError> {
+        // Spans, which are dropped without end time, end now.
+        cx.span().end_with_timestamp(end_time);
+        self.unflushed += 1;
+        if self.unflushed == self.flush_interval {
+            pipeline::force_flush_traces()?;
+            self.unflushed = 0;
+        }
+        Ok(())
+    }
+
+    /// Exports the remaining spans. Fails if OpenTelemetry reported errors in the meantime, e.g.
+    /// because spans were dropped or failed to export in the background.
+    pub(crate) fn finish(self) -> Result<(), ImportError> {
+        pipeline::force_flush_traces()?;
+        match pipeline::reported_errors() - self.reported_errors {
+            0 => Ok(()),
+            errors => Err(TraceError::from(format!(
+                "OpenTelemetry reported {} error(s) during the import, spans may be missing",
+                errors
+            ))
+            .into()),
+        }
+    }
+}
+
+/// A span reconstructed from the timing data of another tool.
+pub(crate) struct ImportedSpan {
+    pub(crate) name: String,
+    pub(crate) start_time: SystemTime,
+    pub(crate) end_time: SystemTime,
+    pub(crate) attributes: Vec<KeyValue>,
+    pub(crate) status: Option<(StatusCode, String)>,
+}
+
+impl ImportedSpan {
+    /// Reports the span as a child of the given context. Returns a context, which can be used as
+    /// parent of nested spans.
+    pub(crate) fn record(
+        self,
+        tracer: &BoxedTracer,
+        recorder: &mut Recorder,
+        parent: &Context,
+    ) -> Result<Context, ImportError> {
+        let end_time = self.end_time;
+        let cx = self.start(tracer, parent);
+        recorder.end(&cx, end_time)?;
+        Ok(cx)
+    }
+
+    /// Starts the span as a child of the given context like `record`, but leaves ending it with
+    /// its end time to the caller, using `Recorder::end`. This allows to update the span based on
+    /// its children.
+    pub(crate) fn start(self, tracer: &BoxedTracer, parent: &Context) -> Context {
+        let span = tracer
+            .span_builder(&self.name)
+            .with_parent_context(parent.clone())
+            .with_start_time(self.start_time)
+            .with_kind(SpanKind::Internal)
+            .with_attributes(self.attributes)
+            .start(tracer);
+        if let Some((code, message)) = self.status {
+            span.set_status(code, message);
+        }
+        parent.with_span(span)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_timestamps() {
+        // 2020-01-20T17:42:40Z
+        let utc = SystemTime::UNIX_EPOCH + Duration::from_secs(1579542160);
+        for (time, expected) in [
+            ("2020-01-20T17:42:40Z", Some(utc)),
+            ("2020-01-20T17:42:40.000Z", Some(utc)),
+            ("2020-01-20T17:42:40", Some(utc)),
+            ("2020-01-20T09:42:40.000-08:00", Some(utc)),
+            ("2020-01-20T09:42:40-08:00", Some(utc)),
+            ("2020-01-20T23:12:40+05:30", Some(utc)),
+            ("2020-01-20T17:42:40+00:00", Some(utc)),
+            ("2020-01-21T03:42:40.000+10:00", Some(utc)),
+            ("2020-01-20T17:42:40+0000", None),
+            ("2020-01-20T17:42:40+xx:00", None),
+            ("2020-01-20", None),
+            // The offset is split off at a byte index, which can be inside a char.
+            ("2020-01-20T17:42:40.€0000", None),
+            ("2020-01-20T17:42:40€", None),
+            ("", None),
+        ] {
+            assert_eq!(parse_timestamp(time), expected, "{}", time);
+        }
+    }
+}
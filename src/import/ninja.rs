@@ -0,0 +1,188 @@
+use super::{ImportError, ImportedSpan, Recorder};
+use opentelemetry::{global::BoxedTracer, Context, Key};
+use std::{
+    borrow::Cow,
+    time::{Duration, SystemTime},
+};
+
+/// An edge of the build graph, which ninja ran. Edges with multiple outputs have one log entry
+/// per output.
+struct Edge {
+    start: Duration,
+    end: Duration,
+    hash: String,
+    outputs: Vec<String>,
+}
+
+/// Imports the last build in a `.ninja_log` file. Ninja records times relative to the start of
+/// the build, which are added to the given start time.
+pub(crate) fn import(
+    tracer: &BoxedTracer,
+    parent: &Context,
+    start_time: SystemTime,
+    input: &str,
+) -> Result<usize, ImportError> {
+    let edges = parse(input)?;
+    let count = edges.len();
+    let mut recorder = Recorder::new();
+    for edge in edges {
+        let mut attributes = vec![
+            Key::new("tracebuild.ninja.output").string(edge.outputs[0].clone()),
+            Key::new("tracebuild.ninja.hash").string(edge.hash),
+        ];
+        if edge.outputs.len() > 1 {
+            attributes.push(
+                Key::new("tracebuild.ninja.outputs").array(
+                    edge.outputs
+                        .iter()
+                        .map(|output| Cow::from(output.clone()))
+                        .collect::<Vec<_>>(),
+                ),
+            );
+        }
+        ImportedSpan {
+            name: format!("ninja - {}", edge.outputs[0]),
+            start_time: start_time + edge.start,
+            end_time: start_time + edge.end,
+            attributes,
+            status: None,
+        }
+        .record(tracer, &mut recorder, parent)?;
+    }
+    recorder.finish()?;
+    Ok(count)
+}
+
+/// Parses the log entries of the last build. The log has the format:
+///
+/// ```text
+/// # ninja log v5
+/// <start ms>\t<end ms>\t<mtime>\t<output>\t<command hash>
+/// ```
+fn parse(input: &str) -> Result<Vec<Edge>, ImportError> {
+    let mut lines = input.lines().enumerate();
+    match lines.next() {
+        Some((_, "# ninja log v5")) | Some((_, "# ninja log v6")) => {}
+        Some((_, header)) => {
+            return Err(ImportError::Invalid(format!(
+                "Unsupported ninja log header {:?}. Supported are versions 5 and 6",
+                header
+            )))
+        }
+        None => return Err(ImportError::Invalid("Empty ninja log".into())),
+    }
+
+    let mut edges = Vec::<Edge>::new();
+    for (index, line) in lines {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let invalid = |message: &str| ImportError::InvalidLine {
+            line: index + 1,
+            message: message.into(),
+        };
+        let fields = line.split('\t').collect::<Vec<_>>();
+        let (start, end, output, hash) = match fields.as_slice() {
+            [start, end, _mtime, output, hash] => (*start, *end, *output, *hash),
+            _ => return Err(invalid("expected 5 tab separated fields")),
+        };
+        let start = Duration::from_millis(
+            start
+                .parse()
+                .map_err(|_| invalid("start time is not a number"))?,
+        );
+        let end = Duration::from_millis(
+            end.parse()
+                .map_err(|_| invalid("end time is not a number"))?,
+        );
+
+        // The log contains all builds since it was last recompacted. Within a build, entries
+        // are written in the order edges finish.
+        if matches!(edges.last(), Some(last) if end < last.end) {
+            edges.clear();
+        }
+        match edges
+            .iter_mut()
+            .rev()
+            .take_while(|edge| edge.end == end)
+            .find(|edge| edge.start == start && edge.end == end && edge.hash == hash)
+        {
+            Some(edge) => edge.outputs.push(output.into()),
+            None => edges.push(Edge {
+                start,
+                end,
+                hash: hash.into(),
+                outputs: vec![output.into()],
+            }),
+        }
+    }
+    Ok(edges)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_last_build() {
+        for (log, expected) in [
+            ("# ninja log v5\n", vec![]),
+            (
+                "# ninja log v5\n0\t10\t0\ta.o\t1\n5\t20\t0\tb.o\t2\n",
+                vec![vec!["a.o"], vec!["b.o"]],
+            ),
+            // Edges with multiple outputs have an entry per output.
+            (
+                "# ninja log v6\n0\t10\t0\ta.h\t1\n0\t10\t0\ta.c\t1\n0\t10\t0\tb.o\t2\n",
+                vec![vec!["a.h", "a.c"], vec!["b.o"]],
+            ),
+            // The end time going backwards starts a new build.
+            (
+                "# ninja log v5\n0\t10\t0\ta.o\t1\n5\t20\t0\tb.o\t2\n0\t5\t0\tb.o\t3\n",
+                vec![vec!["b.o"]],
+            ),
+            (
+                "# ninja log v5\n0\t10\t0\ta.o\t1\n0\t10\t0\ta.o\t1\n0\t8\t0\tc.o\t2\n1\t9\t0\td.o\t3\n",
+                vec![vec!["c.o"], vec!["d.o"]],
+            ),
+            // Empty lines and comments are skipped.
+            (
+                "# ninja log v5\n0\t10\t0\ta.o\t1\n\n# comment\n10\t20\t0\tb.o\t2\n",
+                vec![vec!["a.o"], vec!["b.o"]],
+            ),
+        ] {
+            let edges = parse(log).unwrap();
+            assert_eq!(
+                edges
+                    .iter()
+                    .map(|edge| edge.outputs.iter().map(String::as_str).collect::<Vec<_>>())
+                    .collect::<Vec<_>>(),
+                expected,
+                "{:?}",
+                log
+            );
+        }
+    }
+
+    #[test]
+    fn parses_times() {
+        let edges = parse("# ninja log v5\n1500\t2750\t0\ta.o\tabc\n").unwrap();
+        assert_eq!(edges[0].start, Duration::from_millis(1500));
+        assert_eq!(edges[0].end, Duration::from_millis(2750));
+        assert_eq!(edges[0].hash, "abc");
+    }
+
+    #[test]
+    fn rejects_invalid_logs() {
+        for log in [
+            "",
+            "# ninja log v4\n",
+            "# ninja log v5\n0\t10\ta.o\t1\n",
+            "# ninja log v5\n0\tx\t0\ta.o\t1\n",
+            "# ninja log v5\n-1\t10\t0\ta.o\t1\n",
+        ] {
+            assert!(parse(log).is_err(), "{:?}", log);
+        }
+    }
+}
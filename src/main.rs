@@ -3,24 +3,42 @@
 //! integrate it in your existing telemetry platform.
 #![deny(missing_docs, unreachable_pub, missing_debug_implementations)]
 
+mod ci;
 mod cmd;
 mod context;
 mod id;
+mod import;
+mod output;
 mod pipeline;
+#[cfg(unix)]
+mod pty;
 mod status;
 mod timestamp;
 
-use id::{BuildId, StepId};
+use ci::CiInfo;
+use cmd::{ChildExit, ResourceUsage};
+use id::{BuildId, IdFormat, StepId};
 use opentelemetry::{
+    global::BoxedTracer,
     metrics::Meter,
-    trace::{FutureExt, Span, SpanKind, StatusCode, TraceContextExt, Tracer},
+    trace::{Event, FutureExt, Span, SpanKind, StatusCode, TraceContextExt, Tracer},
     Context, Key, KeyValue, Unit,
 };
+use output::CaptureConfig;
+use regex::Regex;
 use status::Status;
-use std::borrow::Cow;
-use structopt::StructOpt;
+use std::{
+    borrow::Cow,
+    ffi::{OsStr, OsString},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+use structopt::{clap::AppSettings, StructOpt};
 use timestamp::Timestamp;
 
+// Same as coreutils' timeout.
+const EXIT_CODE_TIMED_OUT: i32 = 124;
+
 fn record_event_duration(meter: &Meter, name: &str, start_time: Timestamp, labels: &[KeyValue]) {
     let duration = start_time.system_time().elapsed().unwrap_or_default();
     match meter
@@ -33,12 +51,336 @@ fn record_event_duration(meter: &Meter, name: &str, start_time: Timestamp, label
     }
 }
 
+fn resource_usage_attributes(usage: &ResourceUsage) -> Vec<KeyValue> {
+    vec![
+        Key::new("tracebuild.cmd.user_time").f64(usage.user_time.as_secs_f64()),
+        Key::new("tracebuild.cmd.system_time").f64(usage.system_time.as_secs_f64()),
+        Key::new("tracebuild.cmd.max_rss").i64(usage.max_rss),
+        Key::new("tracebuild.cmd.minor_page_faults").i64(usage.minor_page_faults),
+        Key::new("tracebuild.cmd.major_page_faults").i64(usage.major_page_faults),
+        Key::new("tracebuild.cmd.block_input_ops").i64(usage.block_input_ops),
+        Key::new("tracebuild.cmd.block_output_ops").i64(usage.block_output_ops),
+        Key::new("tracebuild.cmd.voluntary_context_switches").i64(usage.voluntary_context_switches),
+        Key::new("tracebuild.cmd.involuntary_context_switches")
+            .i64(usage.involuntary_context_switches),
+    ]
+}
+
+fn record_resource_usage(meter: &Meter, usage: &ResourceUsage, labels: &[KeyValue]) {
+    for (name, time) in &[
+        ("tracebuild.cmd.user_time", usage.user_time),
+        ("tracebuild.cmd.system_time", usage.system_time),
+    ] {
+        match meter
+            .f64_value_recorder(*name)
+            .with_unit(Unit::new("seconds"))
+            .try_init()
+        {
+            Ok(value_recorder) => value_recorder.record(time.as_secs_f64(), labels),
+            Err(err) => eprintln!("Failed to record {}: {}", name, err),
+        }
+    }
+
+    // A gauge rather than a histogram, since the histogram boundaries are meant for durations.
+    let (max_rss, max_rss_labels) = (usage.max_rss, labels.to_vec());
+    if let Err(err) = meter
+        .i64_value_observer("tracebuild.cmd.max_rss", move |result| {
+            result.observe(max_rss, &max_rss_labels)
+        })
+        .with_unit(Unit::new("bytes"))
+        .try_init()
+    {
+        eprintln!("Failed to record tracebuild.cmd.max_rss: {}", err);
+    }
+
+    for (name, count) in &[
+        ("tracebuild.cmd.minor_page_faults", usage.minor_page_faults),
+        ("tracebuild.cmd.major_page_faults", usage.major_page_faults),
+        ("tracebuild.cmd.block_input_ops", usage.block_input_ops),
+        ("tracebuild.cmd.block_output_ops", usage.block_output_ops),
+        (
+            "tracebuild.cmd.voluntary_context_switches",
+            usage.voluntary_context_switches,
+        ),
+        (
+            "tracebuild.cmd.involuntary_context_switches",
+            usage.involuntary_context_switches,
+        ),
+    ] {
+        match meter.u64_counter(*name).try_init() {
+            Ok(counter) => counter.add(*count as u64, labels),
+            Err(err) => eprintln!("Failed to record {}: {}", name, err),
+        }
+    }
+}
+
+fn cmd_attributes(cmd: &str, args: &[String], script: Option<String>) -> Vec<KeyValue> {
+    let mut attributes = vec![
+        Key::new("tracebuild.cmd.command").string(cmd.to_string()),
+        Key::new("tracebuild.cmd.arguments").array(
+            args.iter()
+                .map(|arg| Cow::from(arg.clone()))
+                .collect::<Vec<_>>(),
+        ),
+    ];
+    if let Some(script) = script {
+        attributes.push(Key::new("tracebuild.cmd.script").string(script));
+    }
+    attributes
+}
+
+/// Settings for a single run of a command.
+struct CmdOptions {
+    timeout: Option<Duration>,
+    grace_period: Duration,
+    capture: Option<CaptureConfig>,
+    output_stats: bool,
+    foreground: bool,
+}
+
+/// Outcome of a single run of a command.
+struct CmdOutcome {
+    exit_code: i32,
+    attributes: Vec<KeyValue>,
+    events: Vec<Event>,
+    /// Error status message
+    error: Option<String>,
+    cancelled: bool,
+}
+
+impl CmdOutcome {
+    fn from_child_exit(
+        child_exit: &ChildExit,
+        start_time: Timestamp,
+        options: &CmdOptions,
+    ) -> Self {
+        let mut attributes = Vec::new();
+        let mut events = Vec::new();
+        let mut error = None;
+        let mut exit_code = match cmd::termination_signal(&child_exit.status) {
+            Some(signal) => {
+                error = Some(format!(
+                    "Command was terminated by signal {}{}",
+                    signal
+                        .name
+                        .map(String::from)
+                        .unwrap_or_else(|| signal.number.to_string()),
+                    if signal.core_dumped {
+                        " (core dumped)"
+                    } else {
+                        ""
+                    }
+                ));
+                attributes.push(Key::new("tracebuild.cmd.signal").i64(signal.number.into()));
+                if let Some(name) = signal.name {
+                    attributes.push(Key::new("tracebuild.cmd.signal_name").string(name));
+                }
+                attributes.push(Key::new("tracebuild.cmd.core_dumped").bool(signal.core_dumped));
+                // Shells report signals as 128 + signal number.
+                128 + signal.number
+            }
+            None => child_exit.status.code().unwrap_or(1),
+        };
+        if child_exit.timed_out {
+            let elapsed = start_time.system_time().elapsed().unwrap_or_default();
+            attributes.push(Key::new("tracebuild.cmd.timed_out").bool(true));
+            error = Some(format!(
+                "Command timed out after {}",
+                humantime::format_duration(Duration::from_secs(elapsed.as_secs()))
+            ));
+            exit_code = EXIT_CODE_TIMED_OUT;
+        }
+        if let Some(signal) = child_exit.cancelled_by {
+            attributes.push(Key::new("tracebuild.cmd.cancelled").bool(true));
+            error = Some(if child_exit.killed {
+                format!(
+                    "Command was cancelled by {} and killed after {}",
+                    signal,
+                    humantime::format_duration(options.grace_period)
+                )
+            } else {
+                format!("Command was cancelled by {}", signal)
+            });
+        }
+        attributes.push(Key::new("tracebuild.cmd.exit_code").i64(exit_code.into()));
+        if let Some(output) = &child_exit.output {
+            events.extend(output.events.iter().cloned());
+            if matches!(&options.capture, Some(config) if config.pty) {
+                attributes.extend(vec![
+                    Key::new("tracebuild.cmd.terminal.lines").i64(output.terminal.lines as i64),
+                    Key::new("tracebuild.cmd.terminal.bytes").i64(output.terminal.bytes as i64),
+                ]);
+            } else if options.output_stats {
+                attributes.extend(vec![
+                    Key::new("tracebuild.cmd.stdout.lines").i64(output.stdout.lines as i64),
+                    Key::new("tracebuild.cmd.stdout.bytes").i64(output.stdout.bytes as i64),
+                    Key::new("tracebuild.cmd.stderr.lines").i64(output.stderr.lines as i64),
+                    Key::new("tracebuild.cmd.stderr.bytes").i64(output.stderr.bytes as i64),
+                ]);
+            }
+            let tail = output.tail();
+            if exit_code != 0 && !tail.is_empty() {
+                events.push(Event::new(
+                    "output",
+                    SystemTime::now(),
+                    vec![Key::new("tracebuild.cmd.output.tail").string(tail)],
+                ));
+            }
+        }
+
+        Self {
+            exit_code,
+            attributes,
+            events,
+            error,
+            cancelled: child_exit.cancelled_by.is_some(),
+        }
+    }
+
+    fn record(&self, span: &dyn Span) {
+        for attribute in &self.attributes {
+            span.set_attribute(attribute.clone());
+        }
+        for event in &self.events {
+            span.add_event_with_timestamp(
+                event.name.to_string(),
+                event.timestamp,
+                event.attributes.clone(),
+            );
+        }
+        if let Some(error) = &self.error {
+            span.set_status(StatusCode::Error, error.clone());
+        }
+    }
+}
+
+/// Runs the command once as part of the span in the given context.
+async fn run_cmd(
+    cx: &Context,
+    build: &BuildId,
+    cmd: &str,
+    args: &[String],
+    options: &CmdOptions,
+) -> CmdOutcome {
+    // Make the span the parent of nested tracebuild invocations and other OpenTelemetry
+    // instrumented programs.
+    let span_context = cx.span().span_context();
+    let envs = vec![
+        ("TRACEPARENT", context::traceparent(span_context)),
+        ("TRACESTATE", span_context.trace_state().header()),
+        ("TRACEBUILD_BUILD_ID", build.to_string()),
+        (
+            "TRACEBUILD_STEP_ID",
+            StepId::from_span_context(span_context).to_string(),
+        ),
+    ];
+
+    let start_time = Timestamp::now();
+    match cmd::fork_and_forward_signals(
+        cmd.into(),
+        args.into(),
+        envs,
+        options.timeout,
+        options.grace_period,
+        options.capture.clone(),
+        options.foreground,
+    )
+    .with_context(cx.clone())
+    .await
+    {
+        Ok(child_exit) => CmdOutcome::from_child_exit(&child_exit, start_time, options),
+        Err(err) => {
+            eprintln!("{}", err);
+            cx.span().record_exception(&err);
+            CmdOutcome {
+                exit_code: err.suggested_exit_code(),
+                attributes: Vec::new(),
+                events: Vec::new(),
+                error: Some(err.to_string()),
+                cancelled: false,
+            }
+        }
+    }
+}
+
+/// Starts the span of a command run by `tracebuild cmd` or `tracebuild sh`.
+fn start_cmd_span(
+    tracer: &BoxedTracer,
+    ci: &CiInfo,
+    parent: Context,
+    name: &str,
+    mut attributes: Vec<KeyValue>,
+) -> Context {
+    attributes.extend(ci.attributes());
+    let span = tracer
+        .span_builder(&format!("cmd - {}", name))
+        .with_parent_context(parent)
+        .with_kind(SpanKind::Client)
+        .with_attributes(attributes)
+        .start(tracer);
+    Context::current_with_span(span)
+}
+
+/// Records the outcome and the resource usage of all commands run so far on the command's span
+/// and reports the command's metrics. Returns the labels of the metrics.
+fn record_cmd_outcome(
+    cx: &Context,
+    meter: &Meter,
+    outcome: &mut CmdOutcome,
+    start_time: Timestamp,
+    name: String,
+    build_name: Option<String>,
+) -> Vec<KeyValue> {
+    let resource_usage = cmd::children_resource_usage();
+    if let Some(usage) = &resource_usage {
+        outcome.attributes.extend(resource_usage_attributes(usage));
+    }
+    outcome.record(cx.span());
+
+    let mut labels = vec![Key::new("name").string(name)];
+    if let Some(build_name) = build_name {
+        labels.push(Key::new("build_name").string(build_name));
+    }
+    labels.push(Key::new("exit_code").i64(outcome.exit_code.into()));
+    record_event_duration(meter, "tracebuild.cmd.duration", start_time, &labels);
+    if let Some(usage) = &resource_usage {
+        record_resource_usage(meter, usage, &labels);
+    }
+    labels
+}
+
 #[derive(StructOpt)]
 enum Args {
     /// Generates an ID, which can be used as either a span or build id.
-    Id,
+    Id {
+        /// Derive the ID from the given seed instead of generating a random one. The same seed
+        /// always results in the same ID.
+        #[structopt(long = "from")]
+        from: Option<String>,
+        /// Derive the ID from the detected CI run id and attempt, so all jobs of a CI run share
+        /// the same ID. If combined with --from, the seed is appended to the CI run.
+        #[structopt(long = "from-ci")]
+        from_ci: bool,
+        /// Use the trace id of the given parent, e.g. a W3C traceparent of a deploy pipeline. Pass
+        /// the same value to `tracebuild build --parent` to parent the build under it.
+        #[structopt(long = "parent")]
+        parent: Option<StepId>,
+        /// Output format: tracebuild or traceparent
+        #[structopt(long = "format", default_value = "tracebuild")]
+        format: IdFormat,
+    },
     /// Generates timestamp, which can be used as a build or span start time.
     Now,
+    /// Prints the W3C traceparent header value for the given build and optional step, which can
+    /// be passed to other tools to continue the trace.
+    Traceparent {
+        /// Build ID
+        #[structopt(long = "build", env = "TRACEBUILD_BUILD_ID")]
+        build: BuildId,
+        /// Optional step ID
+        #[structopt(long = "step", env = "TRACEBUILD_STEP_ID")]
+        step: Option<StepId>,
+    },
     /// Executes the specified command and reports a span using the configured OpenTelemetry
     /// exporter.
     Cmd {
@@ -51,16 +393,109 @@ enum Args {
         /// Optional name. Falls back to cmd + args. Included in metrics, so should be low cardinality if metrics are enabled.
         #[structopt(long = "name")]
         name: Option<String>,
-        /// Optional build name
+        /// Optional build name. Falls back to the detected CI pipeline name.
         #[structopt(long = "build-name", env = "TRACEBUILD_BUILD_NAME")]
         build_name: Option<String>,
+        /// Optional service name. Falls back to the service.name in OTEL_RESOURCE_ATTRIBUTES and
+        /// then to "tracebuild".
+        #[structopt(long = "service-name", env = "OTEL_SERVICE_NAME")]
+        service_name: Option<String>,
+        /// Optional timeout, e.g. "20m". If the command runs longer, it gets a SIGTERM and
+        /// tracebuild exits with 124.
+        #[structopt(long = "timeout", parse(try_from_str = humantime::parse_duration))]
+        timeout: Option<Duration>,
+        /// Number of times to retry the command if it fails, so it runs at most N + 1 times. Each
+        /// attempt is reported as a child span of the cmd span.
+        #[structopt(long = "retries", default_value = "0")]
+        retries: u32,
+        /// Only retry if the command exits with one of these comma separated exit codes. Retries
+        /// on all non-zero exit codes by default.
+        #[structopt(long = "retry-on-exit-code", use_delimiter = true)]
+        retry_on_exit_codes: Vec<i32>,
+        /// Time to wait between attempts
+        #[structopt(long = "retry-delay", default_value = "0s", parse(try_from_str = humantime::parse_duration))]
+        retry_delay: Duration,
+        /// Attach the last N lines of stdout and stderr as span event if the command fails. This
+        /// captures the output, which means the command no longer writes to a terminal directly.
+        #[structopt(long = "output-tail", default_value = "0")]
+        output_tail: usize,
+        /// Maximum size of the output tail in bytes
+        #[structopt(long = "output-tail-max-bytes", default_value = "8192")]
+        output_tail_max_bytes: usize,
+        /// Always attach the number of lines and bytes written to stdout and stderr. This captures
+        /// the output, which means the command no longer writes to a terminal directly.
+        #[structopt(long = "output-stats")]
+        output_stats: bool,
+        /// Create a span event for every output line matching the regular expression, with the
+        /// capture groups as attributes. Can be specified multiple times. This captures the
+        /// output, which means the command no longer writes to a terminal directly.
+        #[structopt(long = "event-pattern", number_of_values = 1, parse(try_from_str = Regex::new))]
+        event_patterns: Vec<Regex>,
+        /// Run the command in a pseudo-terminal, so it keeps colors and progress bars, while
+        /// tracebuild records the number of lines and bytes it writes. Stdout and stderr are
+        /// combined. Only supported on unix.
+        #[structopt(long = "pty")]
+        pty: bool,
+        /// Time the command gets to exit after tracebuild forwarded a SIGHUP, SIGINT, SIGQUIT or
+        /// SIGTERM to it, before it gets killed.
+        #[structopt(long = "grace-period", default_value = "10s", parse(try_from_str = humantime::parse_duration))]
+        grace_period: Duration,
+        /// Make the command the foreground job of the terminal, so it can read from it. Ctrl-C
+        /// then goes to the command directly instead of through tracebuild, which means it
+        /// doesn't cancel the command and no grace period applies. Only has an effect on unix.
+        #[structopt(long = "foreground")]
+        foreground: bool,
+        /// Run the given script using $SHELL (or sh if unset) instead of CMD, e.g. for pipelines.
+        /// The span is named after the script.
+        #[structopt(long = "shell", conflicts_with = "CMD")]
+        shell: Option<String>,
         /// Command name
-        #[structopt(name = "CMD")]
-        cmd: String,
+        #[structopt(name = "CMD", required_unless = "shell")]
+        cmd: Option<String>,
         /// Command arguments
         #[structopt(name = "ARGS")]
         args: Vec<String>,
     },
+    /// Runs a shell script like sh and reports a cmd span for it. Meant to be used as SHELL in
+    /// make (`make SHELL='tracebuild sh'`), so every recipe line becomes a span. Also runs when
+    /// tracebuild is invoked as `tracebuild-sh`. Without build ID, the script runs untraced.
+    #[structopt(
+        setting = AppSettings::TrailingVarArg,
+        setting = AppSettings::AllowLeadingHyphen
+    )]
+    Sh {
+        /// Build ID
+        #[structopt(long = "build", env = "TRACEBUILD_BUILD_ID")]
+        build: Option<BuildId>,
+        /// Optional parent step ID
+        #[structopt(long = "step", env = "TRACEBUILD_STEP_ID")]
+        step: Option<StepId>,
+        /// Environment variable containing the name, e.g. the make target. Falls back to the
+        /// script.
+        #[structopt(
+            long = "name-env",
+            env = "TRACEBUILD_SH_NAME_ENV",
+            default_value = "TRACEBUILD_TARGET"
+        )]
+        name_env: String,
+        /// Optional build name. Falls back to the detected CI pipeline name.
+        #[structopt(long = "build-name", env = "TRACEBUILD_BUILD_NAME")]
+        build_name: Option<String>,
+        /// Optional service name. Falls back to the service.name in OTEL_RESOURCE_ATTRIBUTES and
+        /// then to "tracebuild".
+        #[structopt(long = "service-name", env = "OTEL_SERVICE_NAME")]
+        service_name: Option<String>,
+        /// Shell running the script
+        #[structopt(long = "shell", env = "TRACEBUILD_SH_SHELL", default_value = "/bin/sh")]
+        shell: String,
+        /// Time the script gets to exit after tracebuild forwarded a signal, which cancels it,
+        /// before it gets killed
+        #[structopt(long = "grace-period", default_value = "10s", parse(try_from_str = humantime::parse_duration))]
+        grace_period: Duration,
+        /// Shell arguments, usually -c and the script
+        #[structopt(name = "ARGS")]
+        args: Vec<String>,
+    },
     /// Reports a span using the configured OpenTelemetry exporter with references to the given
     /// build and optional parent step.
     Step {
@@ -79,13 +514,20 @@ enum Args {
         /// Optional name
         #[structopt(long = "name")]
         name: Option<String>,
-        /// Optional build name
+        /// Optional build name. Falls back to the detected CI pipeline name.
         #[structopt(long = "build-name", env = "TRACEBUILD_BUILD_NAME")]
         build_name: Option<String>,
         /// Optional status
         #[structopt(long = "status")]
         status: Option<Status>,
+        /// Optional service name. Falls back to the service.name in OTEL_RESOURCE_ATTRIBUTES and
+        /// then to "tracebuild".
+        #[structopt(long = "service-name", env = "OTEL_SERVICE_NAME")]
+        service_name: Option<String>,
     },
+    /// Reports spans for the timing data of other tools using the configured OpenTelemetry
+    /// exporter.
+    Import(Import),
     /// Reports a span using the configured OpenTelemetry exporter with the given ID and metadata.
     Build {
         /// Build ID
@@ -94,88 +536,455 @@ enum Args {
         /// Start time
         #[structopt(long = "start-time", env = "TRACEBUILD_BUILD_START")]
         start_time: Timestamp,
-        /// Optional name
+        /// Optional name. Falls back to the detected CI pipeline name.
         #[structopt(long = "name", env = "TRACEBUILD_BUILD_NAME")]
         name: Option<String>,
-        /// Optional branch name. Included in metrics, so should be low cardinality if metrics are enabled.
+        /// Optional branch name. Falls back to the detected CI branch. Included in metrics, so should be low cardinality if metrics are enabled.
         #[structopt(long = "branch")]
         branch: Option<String>,
-        /// Optioanl commit SHA
+        /// Optional commit SHA. Falls back to the detected CI commit.
         #[structopt(long = "commit")]
         commit: Option<String>,
+        /// Optional parent span, e.g. a W3C traceparent of a deploy pipeline. The build ID must
+        /// have the same trace id, see `tracebuild id --parent`.
+        #[structopt(long = "parent")]
+        parent: Option<StepId>,
         /// Optional status
         #[structopt(long = "status")]
         status: Option<Status>,
+        /// Optional service name. Falls back to the service.name in OTEL_RESOURCE_ATTRIBUTES and
+        /// then to "tracebuild".
+        #[structopt(long = "service-name", env = "OTEL_SERVICE_NAME")]
+        service_name: Option<String>,
     },
 }
 
+impl Args {
+    fn service_name(&self) -> Option<String> {
+        match self {
+            Args::Id { .. } | Args::Now | Args::Traceparent { .. } => None,
+            Args::Cmd { service_name, .. }
+            | Args::Sh { service_name, .. }
+            | Args::Step { service_name, .. }
+            | Args::Build { service_name, .. } => service_name.clone(),
+            Args::Import(import) => import.service_name(),
+        }
+    }
+}
+
+#[derive(StructOpt)]
+enum Import {
+    /// Reports a span for every edge of the last build in a .ninja_log file.
+    Ninja {
+        /// Build ID
+        #[structopt(long = "build", env = "TRACEBUILD_BUILD_ID")]
+        build: BuildId,
+        /// Optional parent step ID
+        #[structopt(long = "step", env = "TRACEBUILD_STEP_ID")]
+        step: Option<StepId>,
+        /// Start time of the ninja build. Times in the log are relative to it.
+        #[structopt(long = "start-time", env = "TRACEBUILD_STEP_START")]
+        start_time: Timestamp,
+        /// Optional service name. Falls back to the service.name in OTEL_RESOURCE_ATTRIBUTES and
+        /// then to "tracebuild".
+        #[structopt(long = "service-name", env = "OTEL_SERVICE_NAME")]
+        service_name: Option<String>,
+        /// Path to the .ninja_log file or - for stdin
+        #[structopt(name = "FILE", parse(from_os_str))]
+        path: PathBuf,
+    },
+    /// Reports a span for every complete (X) and begin/end (B/E) event in a Chrome trace-event
+    /// profile, as written by Bazel, Clang -ftime-trace or TypeScript --generateTrace.
+    ChromeTrace {
+        /// Build ID
+        #[structopt(long = "build", env = "TRACEBUILD_BUILD_ID")]
+        build: BuildId,
+        /// Optional parent step ID
+        #[structopt(long = "step", env = "TRACEBUILD_STEP_ID")]
+        step: Option<StepId>,
+        /// Start time of the profiled tool. The earliest event is aligned with it.
+        #[structopt(long = "start-time", env = "TRACEBUILD_STEP_START")]
+        start_time: Timestamp,
+        /// Optional service name. Falls back to the service.name in OTEL_RESOURCE_ATTRIBUTES and
+        /// then to "tracebuild".
+        #[structopt(long = "service-name", env = "OTEL_SERVICE_NAME")]
+        service_name: Option<String>,
+        /// Path to the JSON profile or - for stdin
+        #[structopt(name = "FILE", parse(from_os_str))]
+        path: PathBuf,
+    },
+    /// Reports a span for every compilation unit in the JSON messages of
+    /// `cargo build --timings=json`. Piped messages are timed when they are read. Units in files
+    /// are laid out one after another from the start time.
+    CargoTimings {
+        /// Build ID
+        #[structopt(long = "build", env = "TRACEBUILD_BUILD_ID")]
+        build: BuildId,
+        /// Optional parent step ID
+        #[structopt(long = "step", env = "TRACEBUILD_STEP_ID")]
+        step: Option<StepId>,
+        /// Start time of the cargo build. Required for files, which contain only durations.
+        #[structopt(long = "start-time", env = "TRACEBUILD_STEP_START")]
+        start_time: Option<Timestamp>,
+        /// Optional service name. Falls back to the service.name in OTEL_RESOURCE_ATTRIBUTES and
+        /// then to "tracebuild".
+        #[structopt(long = "service-name", env = "OTEL_SERVICE_NAME")]
+        service_name: Option<String>,
+        /// Path to a file with JSON messages or - for stdin
+        #[structopt(name = "FILE", parse(from_os_str), default_value = "-")]
+        path: PathBuf,
+    },
+    /// Reports a span for every test suite and test case in a JUnit XML report and records test
+    /// metrics.
+    Junit {
+        /// Build ID
+        #[structopt(long = "build", env = "TRACEBUILD_BUILD_ID")]
+        build: BuildId,
+        /// Optional parent step ID
+        #[structopt(long = "step", env = "TRACEBUILD_STEP_ID")]
+        step: Option<StepId>,
+        /// Optional start time of the tests, used for test suites without timestamp. Falls back
+        /// to now minus the duration of all tests.
+        #[structopt(long = "start-time", env = "TRACEBUILD_STEP_START")]
+        start_time: Option<Timestamp>,
+        /// Optional build name. Falls back to the detected CI pipeline name.
+        #[structopt(long = "build-name", env = "TRACEBUILD_BUILD_NAME")]
+        build_name: Option<String>,
+        /// Optional service name. Falls back to the service.name in OTEL_RESOURCE_ATTRIBUTES and
+        /// then to "tracebuild".
+        #[structopt(long = "service-name", env = "OTEL_SERVICE_NAME")]
+        service_name: Option<String>,
+        /// Path to the XML report or - for stdin
+        #[structopt(name = "FILE", parse(from_os_str))]
+        path: PathBuf,
+    },
+    /// Reports a span for every test in libtest's JSON output, as written by
+    /// `cargo test -- -Z unstable-options --format json` or nextest. Tests are timed when their
+    /// events are read, so pipe the output into tracebuild. In files, only tests with exec time
+    /// have a duration.
+    LibtestJson {
+        /// Build ID
+        #[structopt(long = "build", env = "TRACEBUILD_BUILD_ID")]
+        build: BuildId,
+        /// Optional parent step ID
+        #[structopt(long = "step", env = "TRACEBUILD_STEP_ID")]
+        step: Option<StepId>,
+        /// Optional service name. Falls back to the service.name in OTEL_RESOURCE_ATTRIBUTES and
+        /// then to "tracebuild".
+        #[structopt(long = "service-name", env = "OTEL_SERVICE_NAME")]
+        service_name: Option<String>,
+        /// Path to a file with JSON events or - for stdin
+        #[structopt(name = "FILE", parse(from_os_str), default_value = "-")]
+        path: PathBuf,
+    },
+    /// Reports a build span with job and step spans for a GitHub Actions workflow run from the
+    /// JSON responses of the workflow run and jobs REST API.
+    GithubActions {
+        /// Optional build ID. Falls back to the ID `tracebuild id --from-ci` generates in the
+        /// run.
+        #[structopt(long = "build")]
+        build: Option<BuildId>,
+        /// Optional service name. Falls back to the service.name in OTEL_RESOURCE_ATTRIBUTES and
+        /// then to "tracebuild".
+        #[structopt(long = "service-name", env = "OTEL_SERVICE_NAME")]
+        service_name: Option<String>,
+        /// Path to the workflow run JSON
+        #[structopt(name = "RUN_FILE", parse(from_os_str))]
+        run_path: PathBuf,
+        /// Path to the jobs JSON of the run
+        #[structopt(name = "JOBS_FILE", parse(from_os_str))]
+        jobs_path: PathBuf,
+    },
+}
+
+impl Import {
+    fn service_name(&self) -> Option<String> {
+        match self {
+            Import::Ninja { service_name, .. }
+            | Import::ChromeTrace { service_name, .. }
+            | Import::CargoTimings { service_name, .. }
+            | Import::Junit { service_name, .. }
+            | Import::LibtestJson { service_name, .. }
+            | Import::GithubActions { service_name, .. } => service_name.clone(),
+        }
+    }
+}
+
+fn import_exit_code(result: Result<usize, import::ImportError>) -> i32 {
+    match result {
+        Ok(_count) => 0,
+        Err(err) => {
+            eprintln!("{}", err);
+            1
+        }
+    }
+}
+
+/// Returns the script passed to a shell with -c, e.g. `sh -ec 'script'`.
+fn shell_script(args: &[String]) -> Option<&String> {
+    let position = args
+        .iter()
+        .position(|arg| arg.starts_with('-') && !arg.starts_with("--") && arg.contains('c'))?;
+    args.get(position + 1)
+}
+
+/// Arguments tracebuild was invoked with. When invoked as `tracebuild-sh`, e.g. through a
+/// symlink, tracebuild behaves like `tracebuild sh`.
+fn process_args() -> Vec<OsString> {
+    let mut args = std::env::args_os().collect::<Vec<_>>();
+    let invoked_as_sh = args.first().and_then(|arg| Path::new(arg).file_stem())
+        == Some(OsStr::new("tracebuild-sh"));
+    if invoked_as_sh {
+        args.insert(1, "sh".into());
+    }
+    args
+}
+
 #[tokio::main(flavor = "multi_thread")]
 async fn main() {
-    pipeline::install_pipeline();
+    let args = Args::from_iter(process_args());
+
+    pipeline::install_pipeline(args.service_name());
     let tracer = pipeline::tracer();
     let meter = pipeline::meter();
+    let ci = ci::detect();
 
-    let args = Args::from_args();
     let exit_code = match args {
-        Args::Id => {
-            let id = BuildId::generate();
-            println!("{}", id);
-            0
+        Args::Id {
+            from,
+            from_ci,
+            parent,
+            format,
+        } => {
+            let seed = if from_ci {
+                match ci.run_seed() {
+                    Some(ci_seed) => Ok(Some(match from {
+                        Some(from) => format!("{}/{}", ci_seed, from),
+                        None => ci_seed,
+                    })),
+                    None => Err("Failed to detect CI run. Supported are: GitHub Actions, GitLab CI, Travis CI, CircleCI, Azure Pipelines, Buildkite, Jenkins"),
+                }
+            } else {
+                Ok(from)
+            };
+            match seed {
+                Ok(seed) => {
+                    let mut id = match seed {
+                        Some(seed) => BuildId::from_seed(&seed),
+                        None => BuildId::generate(),
+                    };
+                    if let Some(parent) = parent {
+                        id = id.with_trace_of(parent.build_id());
+                    }
+                    match format {
+                        IdFormat::Tracebuild => println!("{}", id),
+                        IdFormat::Traceparent => println!(
+                            "{}",
+                            context::traceparent(&context::get_parent_span_context(id, None))
+                        ),
+                    }
+                    0
+                }
+                Err(err) => {
+                    eprintln!("{}", err);
+                    1
+                }
+            }
         }
         Args::Now => {
             let now = Timestamp::now();
             println!("{}", now);
             0
         }
+        Args::Traceparent { build, step } => {
+            println!(
+                "{}",
+                context::traceparent(&context::get_parent_span_context(build, step))
+            );
+            0
+        }
         Args::Cmd {
             build,
             step,
             name,
             build_name,
+            service_name: _,
+            timeout,
+            retries,
+            retry_on_exit_codes,
+            retry_delay,
+            output_tail,
+            output_tail_max_bytes,
+            output_stats,
+            event_patterns,
+            pty,
+            grace_period,
+            foreground,
+            shell,
             cmd,
             args,
         } => {
-            let name = name.unwrap_or_else(|| format!("{} {}", cmd, args.join(" ")));
-            let span = tracer
-                .span_builder(&format!("cmd - {}", name))
-                .with_parent_context(context::get_parent_context(build, step))
-                .with_kind(SpanKind::Client)
-                .with_attributes(vec![
-                    Key::new("tracebuild.cmd.command").string(cmd.clone()),
-                    Key::new("tracebuild.cmd.arguments").array(
-                        args.iter()
-                            .map(|arg| Cow::from(arg.clone()))
-                            .collect::<Vec<_>>(),
-                    ),
-                ])
-                .start(&tracer);
-            let cx = Context::current_with_span(span);
+            let (cmd, args) = match &shell {
+                Some(script) => (cmd::shell(), vec!["-c".into(), script.clone()]),
+                None => (cmd.expect("CMD is required without --shell"), args),
+            };
+            let name = name.unwrap_or_else(|| match &shell {
+                Some(script) => script.clone(),
+                None => format!("{} {}", cmd, args.join(" ")),
+            });
+            let build_name = build_name.or(ci.build_name.clone());
+            let cx = start_cmd_span(
+                &tracer,
+                &ci,
+                context::get_parent_context(build.clone(), step),
+                &name,
+                cmd_attributes(&cmd, &args, shell),
+            );
+
+            let options = CmdOptions {
+                timeout,
+                grace_period,
+                capture: if output_tail > 0 || output_stats || !event_patterns.is_empty() || pty {
+                    Some(CaptureConfig {
+                        tail_lines: output_tail,
+                        tail_max_bytes: output_tail_max_bytes,
+                        event_patterns,
+                        pty,
+                    })
+                } else {
+                    None
+                },
+                output_stats,
+                foreground,
+            };
+
             let start_time = Timestamp::now();
-            let exit_code = match cmd::fork_with_sigterm(cmd, args)
-                .with_context(cx.clone())
-                .await
-            {
-                Ok(exit_status) => {
-                    let exit_code = exit_status.code().unwrap_or(1);
-                    cx.span()
-                        .set_attribute(Key::new("tracebuild.cmd.exit_code").i64(exit_code.into()));
-                    exit_code
+            // The first run is not a retry.
+            let max_attempts = retries.saturating_add(1);
+            let mut attempt = 1;
+            let mut outcome = loop {
+                let outcome = if retries == 0 {
+                    run_cmd(&cx, &build, &cmd, &args, &options).await
+                } else {
+                    let attempt_span = tracer
+                        .span_builder(&format!("attempt {}", attempt))
+                        .with_parent_context(cx.clone())
+                        .with_kind(SpanKind::Client)
+                        .with_attributes(vec![
+                            Key::new("tracebuild.cmd.attempt").i64(attempt.into())
+                        ])
+                        .start(&tracer);
+                    let attempt_cx = Context::current_with_span(attempt_span);
+                    let outcome = run_cmd(&attempt_cx, &build, &cmd, &args, &options).await;
+                    outcome.record(attempt_cx.span());
+                    attempt_cx.span().end();
+                    outcome
+                };
+
+                let retry = attempt < max_attempts
+                    && outcome.exit_code != 0
+                    && !outcome.cancelled
+                    && (retry_on_exit_codes.is_empty()
+                        || retry_on_exit_codes.contains(&outcome.exit_code));
+                if !retry {
+                    break outcome;
                 }
-                Err(err) => {
-                    eprintln!("{}", err);
-                    cx.span().record_exception(&err);
-                    cx.span().set_status(StatusCode::Error, err.to_string());
-                    err.suggested_exit_code()
+
+                if let Some(signal) = cmd::sleep_unless_cancelled(retry_delay).await {
+                    let mut outcome = outcome;
+                    outcome
+                        .attributes
+                        .push(Key::new("tracebuild.cmd.cancelled").bool(true));
+                    outcome.error = Some(format!(
+                        "Command was cancelled by {} before attempt {}",
+                        signal,
+                        attempt + 1
+                    ));
+                    outcome.cancelled = true;
+                    break outcome;
                 }
+                attempt += 1;
             };
 
-            let mut labels = vec![Key::new("name").string(name)];
-            if let Some(build_name) = build_name {
-                labels.push(Key::new("build_name").string(build_name));
+            outcome
+                .attributes
+                .push(Key::new("tracebuild.cmd.attempts").i64(attempt.into()));
+            if retries > 0 {
+                // Events are already recorded on the attempt spans.
+                outcome.events.clear();
+            }
+            // Resource usage includes all attempts.
+            let labels =
+                record_cmd_outcome(&cx, &meter, &mut outcome, start_time, name, build_name);
+            match meter.u64_counter("tracebuild.cmd.attempts").try_init() {
+                Ok(counter) => counter.add(attempt.into(), &labels),
+                Err(err) => eprintln!("Failed to record tracebuild.cmd.attempts: {}", err),
+            }
+            outcome.exit_code
+        }
+        Args::Sh {
+            build,
+            step,
+            name_env,
+            build_name,
+            service_name: _,
+            shell,
+            grace_period,
+            args,
+        } => {
+            let options = CmdOptions {
+                timeout: None,
+                grace_period,
+                capture: None,
+                output_stats: false,
+                foreground: false,
+            };
+            let script = shell_script(&args).cloned();
+            match build {
+                // Not part of a traced build, e.g. make was invoked directly.
+                None => {
+                    let start_time = Timestamp::now();
+                    match cmd::fork_and_forward_signals(
+                        shell,
+                        args,
+                        Vec::new(),
+                        None,
+                        grace_period,
+                        None,
+                        false,
+                    )
+                    .await
+                    {
+                        Ok(child_exit) => {
+                            CmdOutcome::from_child_exit(&child_exit, start_time, &options).exit_code
+                        }
+                        Err(err) => {
+                            eprintln!("{}", err);
+                            err.suggested_exit_code()
+                        }
+                    }
+                }
+                Some(build) => {
+                    let name = std::env::var(&name_env)
+                        .ok()
+                        .filter(|name| !name.is_empty())
+                        .or_else(|| script.clone())
+                        .unwrap_or_else(|| format!("{} {}", shell, args.join(" ")));
+                    let build_name = build_name.or(ci.build_name.clone());
+                    let cx = start_cmd_span(
+                        &tracer,
+                        &ci,
+                        context::get_parent_context(build.clone(), step),
+                        &name,
+                        cmd_attributes(&shell, &args, script),
+                    );
+
+                    let start_time = Timestamp::now();
+                    let mut outcome = run_cmd(&cx, &build, &shell, &args, &options).await;
+                    record_cmd_outcome(&cx, &meter, &mut outcome, start_time, name, build_name);
+                    outcome.exit_code
+                }
             }
-            labels.push(Key::new("exit_code").i64(exit_code.into()));
-            record_event_duration(&meter, "tracebuild.cmd.duration", start_time, &labels);
-            exit_code
         }
         Args::Step {
             build,
@@ -185,7 +994,9 @@ async fn main() {
             name,
             build_name,
             status,
+            service_name: _,
         } => {
+            let build_name = build_name.or(ci.build_name.clone());
             let span_name: Cow<'static, str> = if let Some(name) = name.clone() {
                 format!("step - {}", name).into()
             } else {
@@ -197,6 +1008,7 @@ async fn main() {
                 .with_start_time(start_time.system_time())
                 .with_span_id(id.span_id())
                 .with_kind(SpanKind::Internal)
+                .with_attributes(ci.attributes())
                 .start(&tracer);
             if let Some(status) = &status {
                 span.set_status(status.into(), "".into());
@@ -221,42 +1033,162 @@ async fn main() {
             name,
             branch,
             commit,
+            parent,
             status,
-        } => {
-            let span_name: Cow<'static, str> = if let Some(name) = name.clone() {
-                format!("build - {}", name).into()
-            } else {
-                "build".into()
-            };
-            let span = tracer
-                .span_builder(&span_name)
-                .with_start_time(start_time.system_time())
-                .with_trace_id(id.trace_id())
-                .with_span_id(id.span_id())
-                .with_kind(SpanKind::Internal)
-                .start(&tracer);
-            if let Some(branch) = branch.clone() {
-                span.set_attribute(Key::new("tracebuild.build.branch").string(branch));
-            }
-            if let Some(commit) = commit {
-                span.set_attribute(Key::new("tracebuild.build.commit").string(commit));
-            }
-            if let Some(status) = &status {
-                span.set_status(status.into(), "".into());
+            service_name: _,
+        } => match &parent {
+            Some(parent) if parent.build_id().trace_id() != id.trace_id() => {
+                eprintln!(
+                    "Build ID {} has a different trace id than the parent {}. Use `tracebuild id --parent` to create it",
+                    id, parent
+                );
+                1
             }
+            _ => {
+                let name = name.or(ci.build_name.clone());
+                let branch = branch.or(ci.branch.clone());
+                let commit = commit.or(ci.commit.clone());
+                let span_name: Cow<'static, str> = if let Some(name) = name.clone() {
+                    format!("build - {}", name).into()
+                } else {
+                    "build".into()
+                };
+                let mut span_builder = tracer.span_builder(&span_name);
+                if let Some(parent) = parent {
+                    span_builder = span_builder
+                        .with_parent_context(context::get_parent_context(id.clone(), Some(parent)));
+                }
+                let span = span_builder
+                    .with_start_time(start_time.system_time())
+                    .with_trace_id(id.trace_id())
+                    .with_span_id(id.span_id())
+                    .with_kind(SpanKind::Internal)
+                    .with_attributes(ci.attributes())
+                    .start(&tracer);
+                if let Some(branch) = branch.clone() {
+                    span.set_attribute(Key::new("tracebuild.build.branch").string(branch));
+                }
+                if let Some(commit) = commit {
+                    span.set_attribute(Key::new("tracebuild.build.commit").string(commit));
+                }
+                if let Some(status) = &status {
+                    span.set_status(status.into(), "".into());
+                }
 
-            let mut labels = Vec::new();
-            if let Some(name) = name {
-                labels.push(Key::new("name").string(name));
-            }
-            if let Some(branch) = branch {
-                labels.push(Key::new("branch").string(branch));
+                let mut labels = Vec::new();
+                if let Some(name) = name {
+                    labels.push(Key::new("name").string(name));
+                }
+                if let Some(branch) = branch {
+                    labels.push(Key::new("branch").string(branch));
+                }
+                if let Some(status) = status {
+                    labels.push(Key::new("status").string(status.to_string()));
+                }
+                record_event_duration(&meter, "tracebuild.build.duration", start_time, &labels);
+                0
             }
-            if let Some(status) = status {
-                labels.push(Key::new("status").string(status.to_string()));
+        },
+        Args::Import(Import::Ninja {
+            build,
+            step,
+            start_time,
+            service_name: _,
+            path,
+        }) => {
+            let parent = context::get_parent_context(build, step);
+            let result = import::read_input(&path).and_then(|input| {
+                import::ninja::import(&tracer, &parent, start_time.system_time(), &input)
+            });
+            import_exit_code(result)
+        }
+        Args::Import(Import::ChromeTrace {
+            build,
+            step,
+            start_time,
+            service_name: _,
+            path,
+        }) => {
+            let parent = context::get_parent_context(build, step);
+            let result = import::read_input(&path).and_then(|input| {
+                import::chrome_trace::import(&tracer, &parent, start_time.system_time(), &input)
+            });
+            import_exit_code(result)
+        }
+        Args::Import(Import::CargoTimings {
+            build,
+            step,
+            start_time,
+            service_name: _,
+            path,
+        }) => {
+            let parent = context::get_parent_context(build, step);
+            let result = import::is_file_input(&path)
+                .and_then(|is_file| match (is_file, start_time) {
+                    (false, _) => Ok(None),
+                    (true, Some(start_time)) => Ok(Some(start_time.system_time())),
+                    (true, None) => Err(import::ImportError::Invalid(
+                        "Files contain only durations, so --start-time is required".into(),
+                    )),
+                })
+                .and_then(|start_time| {
+                    let input = import::open_input(&path)?;
+                    import::cargo_timings::import(&tracer, &parent, start_time, input)
+                });
+            import_exit_code(result)
+        }
+        Args::Import(Import::Junit {
+            build,
+            step,
+            start_time,
+            build_name,
+            service_name: _,
+            path,
+        }) => {
+            let parent = context::get_parent_context(build, step);
+            let mut labels = Vec::new();
+            if let Some(build_name) = build_name.or(ci.build_name.clone()) {
+                labels.push(Key::new("build_name").string(build_name));
             }
-            record_event_duration(&meter, "tracebuild.build.duration", start_time, &labels);
-            0
+            let result = import::read_input(&path).and_then(|input| {
+                import::junit::import(
+                    &tracer,
+                    &meter,
+                    &parent,
+                    start_time.map(|start_time| start_time.system_time()),
+                    labels,
+                    &input,
+                )
+            });
+            import_exit_code(result)
+        }
+        Args::Import(Import::LibtestJson {
+            build,
+            step,
+            service_name: _,
+            path,
+        }) => {
+            let parent = context::get_parent_context(build, step);
+            let result = import::is_file_input(&path).and_then(|is_file| {
+                if is_file {
+                    eprintln!("Events in files are read at once, so tests without exec_time take no time. Pipe the output into tracebuild instead.");
+                }
+                let input = import::open_input(&path)?;
+                import::libtest_json::import(&tracer, &parent, input)
+            });
+            import_exit_code(result)
+        }
+        Args::Import(Import::GithubActions {
+            build,
+            service_name: _,
+            run_path,
+            jobs_path,
+        }) => {
+            let result = import::read_input(&run_path).and_then(|run| {
+                let jobs = import::read_input(&jobs_path)?;
+                import::github_actions::import(&tracer, build, &run, &jobs)
+            });
+            import_exit_code(result)
         }
     };
 
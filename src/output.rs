@@ -0,0 +1,303 @@
+#[cfg(unix)]
+use crate::pty::PtyMaster;
+use opentelemetry::{trace::Event, Key};
+use regex::Regex;
+use std::{
+    collections::VecDeque,
+    io,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt as _, AsyncWrite, AsyncWriteExt as _},
+    process::Child,
+    task::JoinHandle,
+};
+
+/// How long to wait for the output pipes to close after the child exited. Descendants of the
+/// child may keep them open, e.g. background processes.
+const PIPE_CLOSE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Maximum number of events created from output lines matching an event pattern. Protects against
+/// patterns matching every line of a very chatty command.
+const MAX_EVENTS: usize = 1000;
+
+/// Maximum length of an event name in bytes.
+const MAX_EVENT_NAME_BYTES: usize = 1024;
+
+/// Settings for capturing the output of a command.
+#[derive(Clone, Debug)]
+pub(crate) struct CaptureConfig {
+    /// Number of output lines to keep.
+    pub(crate) tail_lines: usize,
+    /// Maximum size of the kept output lines in bytes.
+    pub(crate) tail_max_bytes: usize,
+    /// Output lines matching any of these patterns are turned into events.
+    pub(crate) event_patterns: Vec<Regex>,
+    /// Run the child in a pseudo-terminal and capture its combined output from there.
+    pub(crate) pty: bool,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Stream {
+    Stdout,
+    Stderr,
+    /// Combined stdout and stderr written to a pseudo-terminal.
+    Terminal,
+}
+
+impl Stream {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Stream::Stdout => "stdout",
+            Stream::Stderr => "stderr",
+            Stream::Terminal => "terminal",
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct StreamStats {
+    pub(crate) lines: u64,
+    pub(crate) bytes: u64,
+    /// Incomplete last line.
+    partial_line: Vec<u8>,
+}
+
+/// Output of a command. Only keeps the last lines of stdout and stderr combined.
+#[derive(Debug)]
+pub(crate) struct CapturedOutput {
+    pub(crate) stdout: StreamStats,
+    pub(crate) stderr: StreamStats,
+    pub(crate) terminal: StreamStats,
+    tail: VecDeque<String>,
+    tail_bytes: usize,
+    /// Events created from lines matching an event pattern, in the order they were printed.
+    pub(crate) events: Vec<Event>,
+    config: CaptureConfig,
+}
+
+impl CapturedOutput {
+    fn new(config: CaptureConfig) -> Self {
+        Self {
+            stdout: StreamStats::default(),
+            stderr: StreamStats::default(),
+            terminal: StreamStats::default(),
+            tail: VecDeque::new(),
+            tail_bytes: 0,
+            events: Vec::new(),
+            config,
+        }
+    }
+
+    /// Last lines of the output, separated by newlines.
+    pub(crate) fn tail(&self) -> String {
+        self.tail.iter().cloned().collect::<Vec<_>>().join("\n")
+    }
+
+    fn stats(&mut self, stream: Stream) -> &mut StreamStats {
+        match stream {
+            Stream::Stdout => &mut self.stdout,
+            Stream::Stderr => &mut self.stderr,
+            Stream::Terminal => &mut self.terminal,
+        }
+    }
+
+    fn write(&mut self, stream: Stream, mut bytes: &[u8]) {
+        self.stats(stream).bytes += bytes.len() as u64;
+        while let Some(pos) = bytes.iter().position(|b| *b == b'\n') {
+            let stats = self.stats(stream);
+            stats.partial_line.extend_from_slice(&bytes[..pos]);
+            let line = std::mem::take(&mut stats.partial_line);
+            self.push_line(stream, &line);
+            bytes = &bytes[pos + 1..];
+        }
+
+        // Only keep as much of very long lines as fits into the tail.
+        let max_bytes = self.config.tail_max_bytes;
+        let stats = self.stats(stream);
+        let remaining = max_bytes.saturating_sub(stats.partial_line.len());
+        stats
+            .partial_line
+            .extend_from_slice(&bytes[..bytes.len().min(remaining)]);
+    }
+
+    fn finish(&mut self, stream: Stream) {
+        let line = std::mem::take(&mut self.stats(stream).partial_line);
+        if !line.is_empty() {
+            self.push_line(stream, &line);
+        }
+    }
+
+    fn push_line(&mut self, stream: Stream, line: &[u8]) {
+        self.stats(stream).lines += 1;
+        if self.config.tail_lines == 0 && self.config.event_patterns.is_empty() {
+            return;
+        }
+
+        let line = String::from_utf8_lossy(line);
+        let line = line.trim_end_matches('\r');
+        self.match_event_patterns(stream, line);
+        if self.config.tail_lines == 0 {
+            return;
+        }
+
+        let line = truncate(line, self.config.tail_max_bytes).to_string();
+        self.tail_bytes += line.len();
+        self.tail.push_back(line);
+        while self.tail.len() > self.config.tail_lines
+            || self.tail_bytes > self.config.tail_max_bytes
+        {
+            if let Some(line) = self.tail.pop_front() {
+                self.tail_bytes -= line.len();
+            }
+        }
+    }
+
+    /// Creates an event for the line if it matches one of the event patterns. The event is named
+    /// after the trimmed line and has the capture groups of the first matching pattern as
+    /// attributes. Named groups use their name as key, other groups their index.
+    fn match_event_patterns(&mut self, stream: Stream, line: &str) {
+        if self.events.len() >= MAX_EVENTS {
+            return;
+        }
+
+        let pattern = self
+            .config
+            .event_patterns
+            .iter()
+            .find_map(|pattern| pattern.captures(line).map(|captures| (pattern, captures)));
+        if let Some((pattern, captures)) = pattern {
+            let mut attributes = vec![
+                Key::new("tracebuild.cmd.output.stream").string(stream.as_str()),
+                Key::new("tracebuild.cmd.output.pattern").string(pattern.as_str().to_string()),
+            ];
+            for (i, name) in pattern.capture_names().enumerate().skip(1) {
+                if let Some(value) = captures.get(i) {
+                    let key = name.map_or_else(|| i.to_string(), String::from);
+                    attributes.push(Key::new(key).string(value.as_str().to_string()));
+                }
+            }
+            self.events.push(Event::new(
+                truncate(line.trim(), MAX_EVENT_NAME_BYTES).to_string(),
+                SystemTime::now(),
+                attributes,
+            ));
+        }
+    }
+}
+
+/// Truncates the string to at most the given number of bytes, respecting char boundaries.
+fn truncate(s: &str, max_bytes: usize) -> &str {
+    if s.len() <= max_bytes {
+        return s;
+    }
+
+    let mut end = max_bytes;
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    &s[..end]
+}
+
+/// Copies stdout and stderr of a child to tracebuild's own stdout and stderr while capturing
+/// them.
+#[derive(Debug)]
+pub(crate) struct OutputTee {
+    output: Arc<Mutex<CapturedOutput>>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl OutputTee {
+    /// Starts copying the output of the child. The child must have been spawned with piped
+    /// stdout and stderr.
+    pub(crate) fn start(child: &mut Child, config: CaptureConfig) -> Self {
+        let output = Arc::new(Mutex::new(CapturedOutput::new(config)));
+        let mut tasks = Vec::new();
+        if let Some(stdout) = child.stdout.take() {
+            tasks.push(spawn_tee(
+                stdout,
+                tokio::io::stdout(),
+                Stream::Stdout,
+                output.clone(),
+            ));
+        }
+        if let Some(stderr) = child.stderr.take() {
+            tasks.push(spawn_tee(
+                stderr,
+                tokio::io::stderr(),
+                Stream::Stderr,
+                output.clone(),
+            ));
+        }
+
+        Self { output, tasks }
+    }
+
+    /// Starts copying the output written to a pseudo-terminal to tracebuild's stdout.
+    #[cfg(unix)]
+    pub(crate) fn start_pty(master: PtyMaster, config: CaptureConfig) -> Self {
+        let output = Arc::new(Mutex::new(CapturedOutput::new(config)));
+        let tasks = vec![spawn_tee(
+            master,
+            tokio::io::stdout(),
+            Stream::Terminal,
+            output.clone(),
+        )];
+
+        Self { output, tasks }
+    }
+
+    /// Waits until the output pipes are closed and returns the captured output. Call after the
+    /// child exited.
+    pub(crate) async fn finish(self) -> CapturedOutput {
+        let deadline = tokio::time::Instant::now() + PIPE_CLOSE_TIMEOUT;
+        for mut task in self.tasks {
+            if tokio::time::timeout_at(deadline, &mut task).await.is_err() {
+                task.abort();
+            }
+        }
+
+        let mut output = self.output.lock().expect("output lock is poisoned");
+        output.finish(Stream::Stdout);
+        output.finish(Stream::Stderr);
+        output.finish(Stream::Terminal);
+        let config = output.config.clone();
+        std::mem::replace(&mut *output, CapturedOutput::new(config))
+    }
+}
+
+fn spawn_tee(
+    reader: impl AsyncRead + Unpin + Send + 'static,
+    writer: impl AsyncWrite + Unpin + Send + 'static,
+    stream: Stream,
+    output: Arc<Mutex<CapturedOutput>>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        if let Err(err) = tee(reader, writer, stream, output).await {
+            eprintln!("Failed to copy output of child process: {}", err);
+        }
+    })
+}
+
+async fn tee(
+    mut reader: impl AsyncRead + Unpin,
+    mut writer: impl AsyncWrite + Unpin,
+    stream: Stream,
+    output: Arc<Mutex<CapturedOutput>>,
+) -> io::Result<()> {
+    let mut buffer = vec![0; 8 * 1024];
+    loop {
+        let n = reader.read(&mut buffer).await?;
+        if n == 0 {
+            return Ok(());
+        }
+
+        writer.write_all(&buffer[..n]).await?;
+        writer.flush().await?;
+        output
+            .lock()
+            .expect("output lock is poisoned")
+            .write(stream, &buffer[..n]);
+    }
+}
@@ -0,0 +1,51 @@
+use crate::pipeline::otlp;
+use async_trait::async_trait;
+use opentelemetry::sdk::export::trace::{ExportResult, SpanData, SpanExporter};
+use std::{
+    fs::OpenOptions,
+    io::{self, Write},
+};
+
+/// Writes every span as a single line of OTLP/JSON.
+pub(crate) struct JsonLinesExporter {
+    writer: Box<dyn Write + Send>,
+}
+
+impl std::fmt::Debug for JsonLinesExporter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("JsonLinesExporter").finish()
+    }
+}
+
+#[async_trait]
+impl SpanExporter for JsonLinesExporter {
+    async fn export(&mut self, batch: Vec<SpanData>) -> ExportResult {
+        // Write the entire batch at once, so lines of multiple processes appending to the same
+        // file don't get mixed up.
+        let mut buffer = Vec::new();
+        for span in batch {
+            serde_json::to_writer(&mut buffer, &otlp::span_to_json(span))
+                .map_err(|err| format!("Failed to encode span: {}", err))?;
+            buffer.push(b'\n');
+        }
+
+        self.writer
+            .write_all(&buffer)
+            .and_then(|_| self.writer.flush())
+            .map_err(|err| format!("Failed to write spans: {}", err))?;
+        Ok(())
+    }
+}
+
+pub(crate) fn new_stdout_exporter() -> JsonLinesExporter {
+    JsonLinesExporter {
+        writer: Box::new(io::stdout()),
+    }
+}
+
+pub(crate) fn new_file_exporter(path: &str) -> io::Result<JsonLinesExporter> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    Ok(JsonLinesExporter {
+        writer: Box::new(file),
+    })
+}
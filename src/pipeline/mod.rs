@@ -1,11 +1,24 @@
+mod json_lines;
+mod otlp;
 mod prometheus;
 
 use opentelemetry::{
     global::BoxedTracer,
     metrics::{Meter, MetricsError},
-    trace::TraceError,
+    sdk::{
+        trace::{Config, Tracer},
+        Resource,
+    },
+    trace::{TraceError, TracerProvider as _},
+    Key, KeyValue,
+};
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    time::Duration,
 };
-use std::sync::Mutex;
 use thiserror::Error;
 
 pub(crate) fn tracer() -> BoxedTracer {
@@ -16,14 +29,105 @@ pub(crate) fn meter() -> Meter {
     opentelemetry::global::meter("tracebuild")
 }
 
+/// Flushes the span processors of the installed trace pipeline and returns the errors of the
+/// exports it triggered.
+pub(crate) fn force_flush_traces() -> Result<(), TraceError> {
+    let tracer = GLOBAL_SDK_TRACER
+        .lock()
+        .expect("GLOBAL_SDK_TRACER Mutex poisoned");
+    if let Some(provider) = tracer.as_ref().and_then(|tracer| tracer.provider()) {
+        for processor in provider.span_processors() {
+            processor.force_flush()?;
+        }
+    }
+    Ok(())
+}
+
+/// Number of errors OpenTelemetry reported to the error handler so far, e.g. for spans, which
+/// were dropped or failed to export in the background.
+pub(crate) fn reported_errors() -> usize {
+    REPORTED_ERRORS.load(Ordering::SeqCst)
+}
+
+/// Maximum number of spans the batch span processor queues before it drops spans.
+pub(crate) fn max_queue_size() -> usize {
+    std::env::var("OTEL_BSP_MAX_QUEUE_SIZE")
+        .ok()
+        .and_then(|size| size.parse().ok())
+        .unwrap_or(DEFAULT_MAX_QUEUE_SIZE)
+}
+
+const DEFAULT_MAX_QUEUE_SIZE: usize = 2048;
+
+const SERVICE_NAME: Key = Key::from_static_str("service.name");
+const DEFAULT_SERVICE_NAME: &str = "tracebuild";
+
+/// Builds the resource reported with all spans and metrics. The service name is taken from the
+/// given value, the `OTEL_RESOURCE_ATTRIBUTES` environment variable or falls back to a default
+/// in this order.
+fn resource(service_name: Option<String>) -> Resource {
+    Resource::new(service_name.map(|name| SERVICE_NAME.string(name)))
+        .merge(&Resource::default())
+        .merge(&Resource::new(vec![KeyValue::new(
+            SERVICE_NAME,
+            DEFAULT_SERVICE_NAME,
+        )]))
+}
+
+fn service_name(resource: &Resource) -> String {
+    resource
+        .iter()
+        .find(|(key, _)| **key == SERVICE_NAME)
+        .map(|(_, value)| value.as_str().into_owned())
+        .unwrap_or_else(|| DEFAULT_SERVICE_NAME.into())
+}
+
+fn trace_config(resource: &Resource) -> Config {
+    opentelemetry::sdk::trace::config().with_resource(resource.clone())
+}
+
+/// Histogram boundaries (in seconds) used for duration metrics.
+fn duration_histogram_boundaries() -> Vec<f64> {
+    vec![
+        1.,    // 1 sec
+        10.,   // 10 secs
+        30.,   // 30 secs
+        60.,   // 1 min
+        300.,  // 5 mins
+        600.,  // 10 mins
+        900.,  // 15 mins
+        1200., // 20 mins
+        1500., // 25 mins
+        1800., // 30 mins
+        2100., // 35 mins
+        2400., // 40 mins
+        2700., // 45 mins
+    ]
+}
+
 lazy_static::lazy_static! {
-    static ref GLOBAL_PROMETHEUS_EXPORTER: Mutex<Option<prometheus::PrometheusPushOnDropExporter>> = Mutex::new(None);
+    // Holds one of the push on drop metrics exporters. They push all recorded metrics once they
+    // are dropped.
+    static ref GLOBAL_METRICS_EXPORTER: Mutex<Option<Box<dyn Send>>> = Mutex::new(None);
+    // Holds the SDK tracer of the installed trace pipeline. Unlike the global tracer, it gives
+    // access to the span processors. It only holds a weak reference to them, so shutting down
+    // the global tracer provider still shuts them down.
+    static ref GLOBAL_SDK_TRACER: Mutex<Option<Tracer>> = Mutex::new(None);
+}
+
+static REPORTED_ERRORS: AtomicUsize = AtomicUsize::new(0);
+
+fn set_global_sdk_tracer(tracer: Tracer) {
+    let mut global_tracer = GLOBAL_SDK_TRACER
+        .lock()
+        .expect("GLOBAL_SDK_TRACER Mutex poisoned");
+    *global_tracer = Some(tracer);
 }
 
-fn set_global_prometheus_exporter(exporter: Option<prometheus::PrometheusPushOnDropExporter>) {
-    let mut global_exporter = GLOBAL_PROMETHEUS_EXPORTER
+fn set_global_metrics_exporter(exporter: Option<Box<dyn Send>>) {
+    let mut global_exporter = GLOBAL_METRICS_EXPORTER
         .lock()
-        .expect("GLOBAL_PROMETHEUS_EXPORTER Mutex poisoned");
+        .expect("GLOBAL_METRICS_EXPORTER Mutex poisoned");
     *global_exporter = exporter;
 }
 
@@ -37,14 +141,15 @@ enum PipelineError {
     Other(String),
 }
 
-pub(crate) fn install_pipeline() {
+pub(crate) fn install_pipeline(service_name: Option<String>) {
     if let Err(err) = opentelemetry::global::set_error_handler(|err| {
+        REPORTED_ERRORS.fetch_add(1, Ordering::SeqCst);
         eprintln!("OpenTelemetry Error: {}", err);
     }) {
         eprintln!("Failed to install OpenTelemetry error handler: {}", err);
     }
 
-    match try_install_chosen_pipeline() {
+    match try_install_chosen_pipeline(&resource(service_name)) {
         Ok(result) => result,
         Err(err) => {
             eprintln!(
@@ -58,23 +163,26 @@ pub(crate) fn install_pipeline() {
 pub(crate) fn shutdown_pipeline() {
     opentelemetry::global::shutdown_tracer_provider();
 
-    set_global_prometheus_exporter(None);
+    set_global_metrics_exporter(None);
     opentelemetry::global::set_meter_provider(
         opentelemetry::metrics::noop::NoopMeterProvider::default(),
     );
 }
 
-fn try_install_chosen_pipeline() -> Result<(), PipelineError> {
+fn try_install_chosen_pipeline(resource: &Resource) -> Result<(), PipelineError> {
     match std::env::var("OTEL_TRACES_EXPORTER")
         .unwrap_or_else(|_| "otlp".into())
         .as_ref()
     {
-        "otlp" => try_install_otlp_traces_pipeline()?,
-        "jaeger" => try_install_jaeger_traces_pipeline()?,
+        "otlp" => try_install_otlp_traces_pipeline(resource)?,
+        "jaeger" => try_install_jaeger_traces_pipeline(resource)?,
+        "zipkin" => try_install_zipkin_traces_pipeline(resource)?,
+        "stdout" => install_json_lines_traces_pipeline(json_lines::new_stdout_exporter(), resource),
+        "file" => try_install_file_traces_pipeline(resource)?,
         "none" => {}
         exporter => {
             return Err(PipelineError::Other(format!(
-                "Unsupported traces exporter {}. Supported are: otlp, jaeger, stdout",
+                "Unsupported traces exporter {}. Supported are: otlp, jaeger, zipkin, stdout, file",
                 exporter
             )))
         }
@@ -84,11 +192,12 @@ fn try_install_chosen_pipeline() -> Result<(), PipelineError> {
         .unwrap_or_else(|_| "none".into())
         .as_ref()
     {
-        "prometheus" => try_install_prometheus_metrics_pipeline()?,
+        "otlp" => try_install_otlp_metrics_pipeline(resource)?,
+        "prometheus" => try_install_prometheus_metrics_pipeline(resource)?,
         "none" => {}
         exporter => {
             return Err(PipelineError::Other(format!(
-                "Unsupported metrics exporter {}. Supported are: otlp, prometheus, stdout",
+                "Unsupported metrics exporter {}. Supported are: otlp, prometheus",
                 exporter
             )))
         }
@@ -97,22 +206,102 @@ fn try_install_chosen_pipeline() -> Result<(), PipelineError> {
     Ok(())
 }
 
-fn try_install_otlp_traces_pipeline() -> Result<(), PipelineError> {
-    let _tracer = opentelemetry_otlp::new_pipeline()
-        .with_env()
-        .with_tonic()
+fn try_install_otlp_traces_pipeline(resource: &Resource) -> Result<(), PipelineError> {
+    match otlp::protocol(otlp::Signal::Traces).map_err(PipelineError::Other)? {
+        otlp::Protocol::Grpc => {
+            let tracer = opentelemetry_otlp::new_pipeline()
+                .with_env()
+                .with_trace_config(trace_config(resource))
+                .with_tonic()
+                .install_batch(opentelemetry::runtime::Tokio)?;
+            set_global_sdk_tracer(tracer);
+        }
+        protocol => {
+            let exporter = otlp::new_otlp_http_exporter(protocol);
+            let provider = opentelemetry::sdk::trace::TracerProvider::builder()
+                .with_default_batch_exporter(exporter, opentelemetry::runtime::Tokio)
+                .with_config(trace_config(resource))
+                .build();
+            install_traces_provider(provider);
+        }
+    };
+    Ok(())
+}
+
+fn try_install_jaeger_traces_pipeline(resource: &Resource) -> Result<(), PipelineError> {
+    let tracer = opentelemetry_jaeger::new_pipeline()
+        .with_service_name(service_name(resource))
+        .with_trace_config(trace_config(resource))
+        .install_batch(opentelemetry::runtime::Tokio)?;
+    set_global_sdk_tracer(tracer);
+    Ok(())
+}
+
+fn try_install_zipkin_traces_pipeline(resource: &Resource) -> Result<(), PipelineError> {
+    let mut pipeline = opentelemetry_zipkin::new_pipeline()
+        .with_service_name(service_name(resource))
+        .with_trace_config(trace_config(resource));
+    if let Ok(endpoint) = std::env::var("OTEL_EXPORTER_ZIPKIN_ENDPOINT") {
+        pipeline = pipeline.with_collector_endpoint(endpoint);
+    }
+    let timeout = std::env::var("OTEL_EXPORTER_ZIPKIN_TIMEOUT")
+        .ok()
+        .and_then(|timeout| timeout.parse().ok())
+        .unwrap_or(10_000);
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_millis(timeout))
+        .build()
+        .map_err(|err| PipelineError::Other(format!("Failed to create HTTP client: {}", err)))?;
+    let tracer = pipeline
+        .with_http_client(client)
         .install_batch(opentelemetry::runtime::Tokio)?;
+    set_global_sdk_tracer(tracer);
+    Ok(())
+}
+
+fn try_install_file_traces_pipeline(resource: &Resource) -> Result<(), PipelineError> {
+    let path = std::env::var("TRACEBUILD_EXPORT_FILE").map_err(|_| {
+        PipelineError::Other("The file exporter requires TRACEBUILD_EXPORT_FILE to be set".into())
+    })?;
+    let exporter = json_lines::new_file_exporter(&path).map_err(|err| {
+        PipelineError::Other(format!("Failed to open export file {}: {}", path, err))
+    })?;
+    install_json_lines_traces_pipeline(exporter, resource);
     Ok(())
 }
 
-fn try_install_jaeger_traces_pipeline() -> Result<(), PipelineError> {
-    let _tracer =
-        opentelemetry_jaeger::new_pipeline().install_batch(opentelemetry::runtime::Tokio)?;
+fn install_json_lines_traces_pipeline(
+    exporter: json_lines::JsonLinesExporter,
+    resource: &Resource,
+) {
+    let provider = opentelemetry::sdk::trace::TracerProvider::builder()
+        .with_default_batch_exporter(exporter, opentelemetry::runtime::Tokio)
+        .with_config(trace_config(resource))
+        .build();
+    install_traces_provider(provider);
+}
+
+fn install_traces_provider(provider: opentelemetry::sdk::trace::TracerProvider) {
+    set_global_sdk_tracer(provider.get_tracer("tracebuild", None));
+    let _ = opentelemetry::global::set_tracer_provider(provider);
+}
+
+fn try_install_otlp_metrics_pipeline(resource: &Resource) -> Result<(), PipelineError> {
+    let protocol = otlp::protocol(otlp::Signal::Metrics).map_err(PipelineError::Other)?;
+    let exporter = otlp::new_otlp_push_on_drop_exporter(
+        protocol,
+        duration_histogram_boundaries(),
+        resource.clone(),
+    );
+    set_global_metrics_exporter(Some(Box::new(exporter)));
     Ok(())
 }
 
-fn try_install_prometheus_metrics_pipeline() -> Result<(), PipelineError> {
-    let exporter = prometheus::new_prometheus_push_on_drop_exporter()?;
-    set_global_prometheus_exporter(Some(exporter));
+fn try_install_prometheus_metrics_pipeline(resource: &Resource) -> Result<(), PipelineError> {
+    let exporter = prometheus::new_prometheus_push_on_drop_exporter(
+        duration_histogram_boundaries(),
+        resource.clone(),
+    )?;
+    set_global_metrics_exporter(Some(Box::new(exporter)));
     Ok(())
 }
@@ -0,0 +1,277 @@
+//! OTLP/JSON follows the proto3 JSON mapping, except that trace and span ids are hex encoded and
+//! enums are always encoded as integers. See
+//! https://github.com/open-telemetry/opentelemetry-specification/blob/main/specification/protocol/otlp.md#json-protobuf-encoding
+
+use super::proto::{
+    collector::{metrics::v1::ExportMetricsServiceRequest, trace::v1::ExportTraceServiceRequest},
+    common::v1::{any_value, AnyValue, InstrumentationLibrary, KeyValue, StringKeyValue},
+    metrics::v1::{metric::Data, DoubleDataPoint, IntDataPoint, Metric, ResourceMetrics},
+    resource::v1::Resource,
+    trace::v1::{span, ResourceSpans, Span},
+};
+use serde_json::{json, Value};
+
+pub(super) fn export_trace_service_request(request: &ExportTraceServiceRequest) -> Value {
+    json!({
+        "resourceSpans": request
+            .resource_spans
+            .iter()
+            .map(resource_spans)
+            .collect::<Vec<_>>(),
+    })
+}
+
+pub(super) fn export_metrics_service_request(request: &ExportMetricsServiceRequest) -> Value {
+    json!({
+        "resourceMetrics": request
+            .resource_metrics
+            .iter()
+            .map(resource_metrics)
+            .collect::<Vec<_>>(),
+    })
+}
+
+/// Encodes the first span in the given resource spans. The span object additionally contains the
+/// span's resource and instrumentation library.
+pub(super) fn standalone_span(resource_spans: &ResourceSpans) -> Value {
+    let library_spans = match resource_spans.instrumentation_library_spans.first() {
+        Some(library_spans) => library_spans,
+        None => return Value::Null,
+    };
+    let mut value = match library_spans.spans.first() {
+        Some(span) => self::span(span),
+        None => return Value::Null,
+    };
+    value["resource"] = resource_spans
+        .resource
+        .as_ref()
+        .map(resource)
+        .unwrap_or_default();
+    value["instrumentationLibrary"] = library_spans
+        .instrumentation_library
+        .as_ref()
+        .map(instrumentation_library)
+        .unwrap_or_default();
+    value
+}
+
+fn resource_spans(resource_spans: &ResourceSpans) -> Value {
+    json!({
+        "resource": resource_spans.resource.as_ref().map(resource),
+        "instrumentationLibrarySpans": resource_spans
+            .instrumentation_library_spans
+            .iter()
+            .map(|library_spans| json!({
+                "instrumentationLibrary": library_spans
+                    .instrumentation_library
+                    .as_ref()
+                    .map(instrumentation_library),
+                "spans": library_spans.spans.iter().map(self::span).collect::<Vec<_>>(),
+            }))
+            .collect::<Vec<_>>(),
+    })
+}
+
+fn resource_metrics(resource_metrics: &ResourceMetrics) -> Value {
+    json!({
+        "resource": resource_metrics.resource.as_ref().map(resource),
+        "instrumentationLibraryMetrics": resource_metrics
+            .instrumentation_library_metrics
+            .iter()
+            .map(|library_metrics| json!({
+                "instrumentationLibrary": library_metrics
+                    .instrumentation_library
+                    .as_ref()
+                    .map(instrumentation_library),
+                "metrics": library_metrics.metrics.iter().map(metric).collect::<Vec<_>>(),
+            }))
+            .collect::<Vec<_>>(),
+    })
+}
+
+fn resource(resource: &Resource) -> Value {
+    json!({
+        "attributes": attributes(&resource.attributes),
+        "droppedAttributesCount": resource.dropped_attributes_count,
+    })
+}
+
+fn instrumentation_library(library: &InstrumentationLibrary) -> Value {
+    json!({
+        "name": library.name,
+        "version": library.version,
+    })
+}
+
+fn span(span: &Span) -> Value {
+    json!({
+        "traceId": to_hex(&span.trace_id),
+        "spanId": to_hex(&span.span_id),
+        "traceState": span.trace_state,
+        "parentSpanId": to_hex(&span.parent_span_id),
+        "name": span.name,
+        "kind": span.kind,
+        "startTimeUnixNano": span.start_time_unix_nano.to_string(),
+        "endTimeUnixNano": span.end_time_unix_nano.to_string(),
+        "attributes": attributes(&span.attributes),
+        "droppedAttributesCount": span.dropped_attributes_count,
+        "events": span.events.iter().map(event).collect::<Vec<_>>(),
+        "droppedEventsCount": span.dropped_events_count,
+        "links": span.links.iter().map(link).collect::<Vec<_>>(),
+        "droppedLinksCount": span.dropped_links_count,
+        "status": span.status.as_ref().map(|status| json!({
+            "message": status.message,
+            "code": status.code,
+        })),
+    })
+}
+
+fn event(event: &span::Event) -> Value {
+    json!({
+        "timeUnixNano": event.time_unix_nano.to_string(),
+        "name": event.name,
+        "attributes": attributes(&event.attributes),
+        "droppedAttributesCount": event.dropped_attributes_count,
+    })
+}
+
+fn link(link: &span::Link) -> Value {
+    json!({
+        "traceId": to_hex(&link.trace_id),
+        "spanId": to_hex(&link.span_id),
+        "traceState": link.trace_state,
+        "attributes": attributes(&link.attributes),
+        "droppedAttributesCount": link.dropped_attributes_count,
+    })
+}
+
+fn metric(metric: &Metric) -> Value {
+    let mut value = json!({
+        "name": metric.name,
+        "description": metric.description,
+        "unit": metric.unit,
+    });
+    let (data_name, data) = match &metric.data {
+        Some(Data::IntSum(sum)) => (
+            "intSum",
+            json!({
+                "dataPoints": sum.data_points.iter().map(int_data_point).collect::<Vec<_>>(),
+                "aggregationTemporality": sum.aggregation_temporality,
+                "isMonotonic": sum.is_monotonic,
+            }),
+        ),
+        Some(Data::DoubleSum(sum)) => (
+            "doubleSum",
+            json!({
+                "dataPoints": sum.data_points.iter().map(double_data_point).collect::<Vec<_>>(),
+                "aggregationTemporality": sum.aggregation_temporality,
+                "isMonotonic": sum.is_monotonic,
+            }),
+        ),
+        Some(Data::IntGauge(gauge)) => (
+            "intGauge",
+            json!({
+                "dataPoints": gauge.data_points.iter().map(int_data_point).collect::<Vec<_>>(),
+            }),
+        ),
+        Some(Data::DoubleGauge(gauge)) => (
+            "doubleGauge",
+            json!({
+                "dataPoints": gauge.data_points.iter().map(double_data_point).collect::<Vec<_>>(),
+            }),
+        ),
+        Some(Data::DoubleHistogram(histogram)) => (
+            "doubleHistogram",
+            json!({
+                "dataPoints": histogram
+                    .data_points
+                    .iter()
+                    .map(|data_point| json!({
+                        "labels": labels(&data_point.labels),
+                        "startTimeUnixNano": data_point.start_time_unix_nano.to_string(),
+                        "timeUnixNano": data_point.time_unix_nano.to_string(),
+                        "count": data_point.count.to_string(),
+                        "sum": data_point.sum,
+                        "bucketCounts": data_point
+                            .bucket_counts
+                            .iter()
+                            .map(|count| count.to_string())
+                            .collect::<Vec<_>>(),
+                        "explicitBounds": data_point.explicit_bounds,
+                    }))
+                    .collect::<Vec<_>>(),
+                "aggregationTemporality": histogram.aggregation_temporality,
+            }),
+        ),
+        // Tracebuild only produces sums, gauges and histograms.
+        _ => return value,
+    };
+    value[data_name] = data;
+    value
+}
+
+fn int_data_point(data_point: &IntDataPoint) -> Value {
+    json!({
+        "labels": labels(&data_point.labels),
+        "startTimeUnixNano": data_point.start_time_unix_nano.to_string(),
+        "timeUnixNano": data_point.time_unix_nano.to_string(),
+        "value": data_point.value.to_string(),
+    })
+}
+
+fn double_data_point(data_point: &DoubleDataPoint) -> Value {
+    json!({
+        "labels": labels(&data_point.labels),
+        "startTimeUnixNano": data_point.start_time_unix_nano.to_string(),
+        "timeUnixNano": data_point.time_unix_nano.to_string(),
+        "value": data_point.value,
+    })
+}
+
+fn labels(labels: &[StringKeyValue]) -> Value {
+    labels
+        .iter()
+        .map(|kv| {
+            json!({
+                "key": kv.key,
+                "value": kv.value,
+            })
+        })
+        .collect()
+}
+
+fn attributes(attributes: &[KeyValue]) -> Value {
+    attributes
+        .iter()
+        .map(|kv| {
+            json!({
+                "key": kv.key,
+                "value": kv.value.as_ref().map(any_value),
+            })
+        })
+        .collect()
+}
+
+fn any_value(value: &AnyValue) -> Value {
+    match &value.value {
+        Some(any_value::Value::StringValue(v)) => json!({ "stringValue": v }),
+        Some(any_value::Value::BoolValue(v)) => json!({ "boolValue": v }),
+        Some(any_value::Value::IntValue(v)) => json!({ "intValue": v.to_string() }),
+        Some(any_value::Value::DoubleValue(v)) => json!({ "doubleValue": v }),
+        Some(any_value::Value::ArrayValue(v)) => json!({
+            "arrayValue": {
+                "values": v.values.iter().map(any_value).collect::<Vec<_>>(),
+            },
+        }),
+        Some(any_value::Value::KvlistValue(v)) => json!({
+            "kvlistValue": {
+                "values": attributes(&v.values),
+            },
+        }),
+        None => json!({}),
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
@@ -0,0 +1,280 @@
+use super::proto::{
+    collector::metrics::v1::{
+        metrics_service_client::MetricsServiceClient, ExportMetricsServiceRequest,
+    },
+    common::v1::{InstrumentationLibrary, KeyValue, StringKeyValue},
+    metrics::v1::{
+        metric::Data, AggregationTemporality, DoubleDataPoint, DoubleGauge, DoubleHistogram,
+        DoubleHistogramDataPoint, DoubleSum, InstrumentationLibraryMetrics, IntDataPoint, IntGauge,
+        IntSum, Metric, ResourceMetrics,
+    },
+    resource::v1::Resource,
+};
+use super::{json, Protocol, Signal};
+use opentelemetry::{
+    metrics::{MetricsError, NumberKind},
+    sdk::{
+        export::metrics::{
+            CheckpointSet as _, Count as _, ExportKind, ExportKindFor as _, ExportKindSelector,
+            Histogram as _, LastValue as _, Record, Sum as _,
+        },
+        metrics::{
+            aggregators::{HistogramAggregator, LastValueAggregator, SumAggregator},
+            controllers::{self, PullController},
+            selectors,
+        },
+    },
+};
+use std::{
+    collections::BTreeMap,
+    time::{Duration, SystemTime},
+};
+
+// Every tracebuild invocation is a separate process, which only reports the measurements it
+// made itself.
+const EXPORT_KIND_SELECTOR: ExportKindSelector = ExportKindSelector::Delta;
+
+pub(crate) struct OtlpPushOnDropExporter {
+    controller: PullController,
+    protocol: Protocol,
+    endpoint: String,
+    timeout: Duration,
+}
+
+impl Drop for OtlpPushOnDropExporter {
+    fn drop(&mut self) {
+        if let Err(err) = self.push_metrics() {
+            opentelemetry::global::handle_error(err);
+        }
+    }
+}
+
+impl OtlpPushOnDropExporter {
+    fn push_metrics(&mut self) -> Result<(), MetricsError> {
+        self.controller.collect()?;
+        let request = export_metrics_service_request(&mut self.controller)?;
+        match self.protocol {
+            Protocol::Grpc => push_metrics_grpc(request, &self.endpoint, self.timeout),
+            Protocol::HttpProtobuf | Protocol::HttpJson => {
+                let (content_type, body) = super::http_body(
+                    self.protocol,
+                    &request,
+                    json::export_metrics_service_request,
+                )
+                .map_err(MetricsError::Other)?;
+                let agent = ureq::AgentBuilder::new().timeout(self.timeout).build();
+                super::send_http(&agent, &self.endpoint, content_type, &body)
+                    .map_err(MetricsError::Other)
+            }
+        }
+    }
+}
+
+pub(crate) fn new_otlp_push_on_drop_exporter(
+    protocol: Protocol,
+    histogram_boundaries: Vec<f64>,
+    resource: opentelemetry::sdk::Resource,
+) -> OtlpPushOnDropExporter {
+    let controller = controllers::pull(
+        Box::new(selectors::simple::Selector::Histogram(histogram_boundaries)),
+        Box::new(EXPORT_KIND_SELECTOR),
+    )
+    .with_cache_period(Duration::from_secs(0))
+    .with_memory(false)
+    .with_resource(resource)
+    .build();
+    opentelemetry::global::set_meter_provider(controller.provider());
+
+    OtlpPushOnDropExporter {
+        controller,
+        protocol,
+        endpoint: super::endpoint(Signal::Metrics, protocol),
+        timeout: super::timeout(Signal::Metrics),
+    }
+}
+
+fn push_metrics_grpc(
+    request: ExportMetricsServiceRequest,
+    endpoint: &str,
+    timeout: Duration,
+) -> Result<(), MetricsError> {
+    let channel = tonic::transport::Channel::from_shared(endpoint.to_string())
+        .map_err(|err| MetricsError::Other(format!("Invalid OTLP endpoint: {}", err)))?
+        .timeout(timeout)
+        .connect_lazy()
+        .map_err(|err| {
+            MetricsError::Other(format!("Failed to connect to OTLP endpoint: {}", err))
+        })?;
+
+    // This runs during shutdown, where we're still inside of the async runtime, but not in an
+    // async context. Block until the metrics are sent to make sure they don't get lost.
+    tokio::task::block_in_place(|| {
+        tokio::runtime::Handle::current().block_on(async {
+            MetricsServiceClient::new(channel)
+                .export(request)
+                .await
+                .map(|_response| ())
+                .map_err(|err| {
+                    MetricsError::Other(format!(
+                        "Failed to send metrics to OTLP endpoint {}: {}",
+                        endpoint, err
+                    ))
+                })
+        })
+    })
+}
+
+fn export_metrics_service_request(
+    controller: &mut PullController,
+) -> Result<ExportMetricsServiceRequest, MetricsError> {
+    let mut resource = None;
+    let mut libraries = BTreeMap::<(String, String), Vec<Metric>>::new();
+    controller.try_for_each(&EXPORT_KIND_SELECTOR, &mut |record| {
+        if resource.is_none() {
+            resource = Some(Resource {
+                attributes: record
+                    .resource()
+                    .iter()
+                    .map(|(key, value)| KeyValue {
+                        key: key.as_str().to_string(),
+                        value: Some(value.clone().into()),
+                    })
+                    .collect(),
+                dropped_attributes_count: 0,
+            });
+        }
+
+        if let Some(metric) = record_to_metric(record)? {
+            let descriptor = record.descriptor();
+            libraries
+                .entry((
+                    descriptor.instrumentation_name().to_string(),
+                    descriptor
+                        .instrumentation_version()
+                        .unwrap_or_default()
+                        .to_string(),
+                ))
+                .or_default()
+                .push(metric);
+        }
+        Ok(())
+    })?;
+
+    Ok(ExportMetricsServiceRequest {
+        resource_metrics: vec![ResourceMetrics {
+            resource,
+            instrumentation_library_metrics: libraries
+                .into_iter()
+                .map(|((name, version), metrics)| InstrumentationLibraryMetrics {
+                    instrumentation_library: Some(InstrumentationLibrary { name, version }),
+                    metrics,
+                })
+                .collect(),
+        }],
+    })
+}
+
+fn record_to_metric(record: &Record<'_>) -> Result<Option<Metric>, MetricsError> {
+    let descriptor = record.descriptor();
+    let aggregator = match record.aggregator() {
+        Some(aggregator) => aggregator,
+        None => return Ok(None),
+    };
+    let kind = descriptor.number_kind();
+    let labels = record
+        .labels()
+        .iter()
+        .map(|(key, value)| StringKeyValue {
+            key: key.as_str().to_string(),
+            value: value.as_str().into_owned(),
+        })
+        .collect::<Vec<_>>();
+    let start_time_unix_nano = to_nanos(*record.start_time());
+    let time_unix_nano = to_nanos(*record.end_time());
+    let temporality: AggregationTemporality = match EXPORT_KIND_SELECTOR.export_kind_for(descriptor)
+    {
+        ExportKind::Cumulative => AggregationTemporality::Cumulative,
+        ExportKind::Delta => AggregationTemporality::Delta,
+    };
+
+    let data = if let Some(histogram) = aggregator.as_any().downcast_ref::<HistogramAggregator>() {
+        let buckets = histogram.histogram()?;
+        Data::DoubleHistogram(DoubleHistogram {
+            data_points: vec![DoubleHistogramDataPoint {
+                labels,
+                start_time_unix_nano,
+                time_unix_nano,
+                count: histogram.count()?,
+                sum: histogram.sum()?.to_f64(kind),
+                bucket_counts: buckets.counts().iter().map(|count| *count as u64).collect(),
+                explicit_bounds: buckets.boundaries().clone(),
+                exemplars: Vec::new(),
+            }],
+            aggregation_temporality: temporality as i32,
+        })
+    } else if let Some(sum) = aggregator.as_any().downcast_ref::<SumAggregator>() {
+        let sum = sum.sum()?;
+        let is_monotonic = descriptor.instrument_kind().monotonic();
+        match kind {
+            NumberKind::F64 => Data::DoubleSum(DoubleSum {
+                data_points: vec![DoubleDataPoint {
+                    labels,
+                    start_time_unix_nano,
+                    time_unix_nano,
+                    value: sum.to_f64(kind),
+                    exemplars: Vec::new(),
+                }],
+                aggregation_temporality: temporality as i32,
+                is_monotonic,
+            }),
+            NumberKind::I64 | NumberKind::U64 => Data::IntSum(IntSum {
+                data_points: vec![IntDataPoint {
+                    labels,
+                    start_time_unix_nano,
+                    time_unix_nano,
+                    value: sum.to_i64(kind),
+                    exemplars: Vec::new(),
+                }],
+                aggregation_temporality: temporality as i32,
+                is_monotonic,
+            }),
+        }
+    } else if let Some(last_value) = aggregator.as_any().downcast_ref::<LastValueAggregator>() {
+        let (value, time) = last_value.last_value()?;
+        match kind {
+            NumberKind::F64 => Data::DoubleGauge(DoubleGauge {
+                data_points: vec![DoubleDataPoint {
+                    labels,
+                    start_time_unix_nano,
+                    time_unix_nano: to_nanos(time),
+                    value: value.to_f64(kind),
+                    exemplars: Vec::new(),
+                }],
+            }),
+            NumberKind::I64 | NumberKind::U64 => Data::IntGauge(IntGauge {
+                data_points: vec![IntDataPoint {
+                    labels,
+                    start_time_unix_nano,
+                    time_unix_nano: to_nanos(time),
+                    value: value.to_i64(kind),
+                    exemplars: Vec::new(),
+                }],
+            }),
+        }
+    } else {
+        return Ok(None);
+    };
+
+    Ok(Some(Metric {
+        name: descriptor.name().to_string(),
+        description: descriptor.description().cloned().unwrap_or_default(),
+        unit: descriptor.unit().unwrap_or_default().to_string(),
+        data: Some(data),
+    }))
+}
+
+fn to_nanos(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos() as u64
+}
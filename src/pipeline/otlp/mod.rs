@@ -0,0 +1,139 @@
+mod json;
+mod metrics;
+mod proto;
+mod traces;
+
+pub(crate) use metrics::new_otlp_push_on_drop_exporter;
+pub(crate) use traces::new_otlp_http_exporter;
+
+use opentelemetry::sdk::export::trace::SpanData;
+use std::time::Duration;
+
+const DEFAULT_GRPC_ENDPOINT: &str = "https://localhost:4317";
+const DEFAULT_HTTP_ENDPOINT: &str = "http://localhost:4318";
+const DEFAULT_TIMEOUT_SECS: u64 = 10;
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Protocol {
+    Grpc,
+    HttpProtobuf,
+    HttpJson,
+}
+
+/// Signal types, which can be configured using signal specific environment variables like
+/// `OTEL_EXPORTER_OTLP_TRACES_ENDPOINT`.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Signal {
+    Traces,
+    Metrics,
+}
+
+impl Signal {
+    fn env_name(&self) -> &'static str {
+        match self {
+            Signal::Traces => "TRACES",
+            Signal::Metrics => "METRICS",
+        }
+    }
+
+    fn http_path(&self) -> &'static str {
+        match self {
+            Signal::Traces => "/v1/traces",
+            Signal::Metrics => "/v1/metrics",
+        }
+    }
+}
+
+/// Converts the span into an OTLP/JSON span object, which also includes the span's resource and
+/// instrumentation library.
+pub(crate) fn span_to_json(span: SpanData) -> serde_json::Value {
+    match proto::resource_spans(vec![span]).first() {
+        Some(resource_spans) => json::standalone_span(resource_spans),
+        None => serde_json::Value::Null,
+    }
+}
+
+fn signal_env_var(signal: Signal, name: &str) -> Option<String> {
+    std::env::var(format!("OTEL_EXPORTER_OTLP_{}_{}", signal.env_name(), name))
+        .or_else(|_| std::env::var(format!("OTEL_EXPORTER_OTLP_{}", name)))
+        .ok()
+}
+
+pub(crate) fn protocol(signal: Signal) -> Result<Protocol, String> {
+    match signal_env_var(signal, "PROTOCOL")
+        .unwrap_or_else(|| "grpc".into())
+        .as_ref()
+    {
+        "grpc" => Ok(Protocol::Grpc),
+        "http/protobuf" => Ok(Protocol::HttpProtobuf),
+        "http/json" => Ok(Protocol::HttpJson),
+        protocol => Err(format!(
+            "Unsupported OTLP protocol {}. Supported are: grpc, http/protobuf, http/json",
+            protocol
+        )),
+    }
+}
+
+fn endpoint(signal: Signal, protocol: Protocol) -> String {
+    // A signal specific endpoint is used as is. For HTTP the generic endpoint is a base URL,
+    // which gets the signal path appended.
+    if let Ok(endpoint) =
+        std::env::var(format!("OTEL_EXPORTER_OTLP_{}_ENDPOINT", signal.env_name()))
+    {
+        return endpoint;
+    }
+
+    match protocol {
+        Protocol::Grpc => std::env::var("OTEL_EXPORTER_OTLP_ENDPOINT")
+            .unwrap_or_else(|_| DEFAULT_GRPC_ENDPOINT.into()),
+        Protocol::HttpProtobuf | Protocol::HttpJson => format!(
+            "{}{}",
+            std::env::var("OTEL_EXPORTER_OTLP_ENDPOINT")
+                .unwrap_or_else(|_| DEFAULT_HTTP_ENDPOINT.into())
+                .trim_end_matches('/'),
+            signal.http_path()
+        ),
+    }
+}
+
+fn timeout(signal: Signal) -> Duration {
+    Duration::from_secs(
+        signal_env_var(signal, "TIMEOUT")
+            .and_then(|timeout| timeout.parse().ok())
+            .unwrap_or(DEFAULT_TIMEOUT_SECS),
+    )
+}
+
+fn send_http(
+    agent: &ureq::Agent,
+    endpoint: &str,
+    content_type: &str,
+    body: &[u8],
+) -> Result<(), String> {
+    agent
+        .post(endpoint)
+        .set("content-type", content_type)
+        .send_bytes(body)
+        .map(|_response| ())
+        .map_err(|err| format!("Failed to send to OTLP HTTP endpoint {}: {}", endpoint, err))
+}
+
+fn http_body<M: prost::Message>(
+    protocol: Protocol,
+    message: &M,
+    to_json: impl FnOnce(&M) -> serde_json::Value,
+) -> Result<(&'static str, Vec<u8>), String> {
+    match protocol {
+        Protocol::HttpJson => Ok((
+            "application/json",
+            to_json(message).to_string().into_bytes(),
+        )),
+        Protocol::Grpc | Protocol::HttpProtobuf => {
+            let mut buffer = Vec::with_capacity(message.encoded_len());
+            message
+                .encode(&mut buffer)
+                .map_err(|err| format!("Failed to encode OTLP request: {}", err))?;
+            Ok(("application/x-protobuf", buffer))
+        }
+    }
+}
@@ -0,0 +1,353 @@
+//! Types generated by `build.rs` from the OTLP protobuf definitions in `proto/`, and conversions
+//! from the SDK's span data.
+
+use opentelemetry::{
+    sdk::{self, export::trace::SpanData},
+    trace::{SpanKind, StatusCode},
+    Array, Value,
+};
+use std::{sync::Arc, time::SystemTime};
+
+// The generated code covers the whole protocol, of which tracebuild uses only parts.
+#[allow(dead_code, unreachable_pub, clippy::all)]
+pub(crate) mod collector {
+    pub(crate) mod metrics {
+        pub(crate) mod v1 {
+            include!(concat!(
+                env!("OUT_DIR"),
+                "/opentelemetry.proto.collector.metrics.v1.rs"
+            ));
+        }
+    }
+
+    pub(crate) mod trace {
+        pub(crate) mod v1 {
+            include!(concat!(
+                env!("OUT_DIR"),
+                "/opentelemetry.proto.collector.trace.v1.rs"
+            ));
+        }
+    }
+}
+
+#[allow(dead_code, unreachable_pub, clippy::all)]
+pub(crate) mod common {
+    pub(crate) mod v1 {
+        include!(concat!(
+            env!("OUT_DIR"),
+            "/opentelemetry.proto.common.v1.rs"
+        ));
+    }
+}
+
+#[allow(dead_code, unreachable_pub, clippy::all)]
+pub(crate) mod metrics {
+    pub(crate) mod v1 {
+        include!(concat!(
+            env!("OUT_DIR"),
+            "/opentelemetry.proto.metrics.v1.rs"
+        ));
+    }
+}
+
+#[allow(dead_code, unreachable_pub, clippy::all)]
+pub(crate) mod resource {
+    pub(crate) mod v1 {
+        include!(concat!(
+            env!("OUT_DIR"),
+            "/opentelemetry.proto.resource.v1.rs"
+        ));
+    }
+}
+
+#[allow(dead_code, unreachable_pub, clippy::all)]
+pub(crate) mod trace {
+    pub(crate) mod v1 {
+        include!(concat!(env!("OUT_DIR"), "/opentelemetry.proto.trace.v1.rs"));
+    }
+}
+
+impl From<Value> for common::v1::AnyValue {
+    fn from(value: Value) -> Self {
+        use common::v1::any_value;
+
+        Self {
+            value: Some(match value {
+                Value::Bool(value) => any_value::Value::BoolValue(value),
+                Value::I64(value) => any_value::Value::IntValue(value),
+                Value::F64(value) => any_value::Value::DoubleValue(value),
+                Value::String(value) => any_value::Value::StringValue(value.into_owned()),
+                Value::Array(array) => any_value::Value::ArrayValue(match array {
+                    Array::Bool(values) => array_value(values),
+                    Array::I64(values) => array_value(values),
+                    Array::F64(values) => array_value(values),
+                    Array::String(values) => array_value(values),
+                }),
+            }),
+        }
+    }
+}
+
+fn array_value<T>(values: Vec<T>) -> common::v1::ArrayValue
+where
+    Value: From<T>,
+{
+    common::v1::ArrayValue {
+        values: values
+            .into_iter()
+            .map(|value| Value::from(value).into())
+            .collect(),
+    }
+}
+
+fn attributes(
+    attributes: impl IntoIterator<Item = (opentelemetry::Key, Value)>,
+) -> Vec<common::v1::KeyValue> {
+    attributes
+        .into_iter()
+        .map(|(key, value)| common::v1::KeyValue {
+            key: key.as_str().to_string(),
+            value: Some(value.into()),
+        })
+        .collect()
+}
+
+fn to_nanos(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos() as u64
+}
+
+/// Groups the spans by resource and instrumentation library.
+pub(crate) fn resource_spans(spans: Vec<SpanData>) -> Vec<trace::v1::ResourceSpans> {
+    type Libraries = Vec<(sdk::InstrumentationLibrary, Vec<trace::v1::Span>)>;
+
+    let mut resources = Vec::<(Arc<sdk::Resource>, Libraries)>::new();
+    for span in spans {
+        let index = match resources.iter().position(|(resource, _)| {
+            Arc::ptr_eq(resource, &span.resource) || resource == &span.resource
+        }) {
+            Some(index) => index,
+            None => {
+                resources.push((span.resource.clone(), Vec::new()));
+                resources.len() - 1
+            }
+        };
+        let libraries = &mut resources[index].1;
+        let library = span.instrumentation_lib;
+        match libraries.iter_mut().find(|(other, _)| *other == library) {
+            Some((_, spans)) => spans.push(span.into()),
+            None => libraries.push((library, vec![span.into()])),
+        }
+    }
+
+    resources
+        .into_iter()
+        .map(|(resource, libraries)| trace::v1::ResourceSpans {
+            resource: Some(resource::v1::Resource {
+                attributes: attributes(
+                    resource
+                        .iter()
+                        .map(|(key, value)| (key.clone(), value.clone())),
+                ),
+                dropped_attributes_count: 0,
+            }),
+            instrumentation_library_spans: libraries
+                .into_iter()
+                .map(|(library, spans)| trace::v1::InstrumentationLibrarySpans {
+                    instrumentation_library: Some(common::v1::InstrumentationLibrary {
+                        name: library.name.to_string(),
+                        version: library.version.unwrap_or_default().to_string(),
+                    }),
+                    spans,
+                })
+                .collect(),
+        })
+        .collect()
+}
+
+impl From<SpanData> for trace::v1::Span {
+    fn from(span: SpanData) -> Self {
+        use trace::v1::{span, status};
+
+        let kind = match span.span_kind {
+            SpanKind::Client => span::SpanKind::Client,
+            SpanKind::Server => span::SpanKind::Server,
+            SpanKind::Producer => span::SpanKind::Producer,
+            SpanKind::Consumer => span::SpanKind::Consumer,
+            SpanKind::Internal => span::SpanKind::Internal,
+        };
+        let status_code = match span.status_code {
+            StatusCode::Unset => status::StatusCode::Unset,
+            StatusCode::Ok => status::StatusCode::Ok,
+            StatusCode::Error => status::StatusCode::Error,
+        };
+        let parent_span_id = if span.parent_span_id.to_u64() != 0 {
+            span.parent_span_id.to_u64().to_be_bytes().to_vec()
+        } else {
+            Vec::new()
+        };
+
+        Self {
+            trace_id: span
+                .span_context
+                .trace_id()
+                .to_u128()
+                .to_be_bytes()
+                .to_vec(),
+            span_id: span.span_context.span_id().to_u64().to_be_bytes().to_vec(),
+            trace_state: span.span_context.trace_state().header(),
+            parent_span_id,
+            name: span.name,
+            kind: kind as i32,
+            start_time_unix_nano: to_nanos(span.start_time),
+            end_time_unix_nano: to_nanos(span.end_time),
+            dropped_attributes_count: span.attributes.dropped_count(),
+            attributes: attributes(span.attributes),
+            dropped_events_count: span.message_events.dropped_count(),
+            events: span
+                .message_events
+                .into_iter()
+                .map(|event| span::Event {
+                    time_unix_nano: to_nanos(event.timestamp),
+                    name: event.name.into_owned(),
+                    attributes: attributes(
+                        event.attributes.into_iter().map(|kv| (kv.key, kv.value)),
+                    ),
+                    dropped_attributes_count: 0,
+                })
+                .collect(),
+            dropped_links_count: span.links.dropped_count(),
+            links: span
+                .links
+                .into_iter()
+                .map(|link| span::Link {
+                    trace_id: link
+                        .span_context()
+                        .trace_id()
+                        .to_u128()
+                        .to_be_bytes()
+                        .to_vec(),
+                    span_id: link
+                        .span_context()
+                        .span_id()
+                        .to_u64()
+                        .to_be_bytes()
+                        .to_vec(),
+                    trace_state: link.span_context().trace_state().header(),
+                    attributes: attributes(
+                        link.attributes()
+                            .iter()
+                            .map(|kv| (kv.key.clone(), kv.value.clone())),
+                    ),
+                    dropped_attributes_count: 0,
+                })
+                .collect(),
+            status: Some(trace::v1::Status {
+                code: status_code as i32,
+                message: span.status_message,
+                ..Default::default()
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry::{
+        trace::{SpanContext, SpanId, TraceId, TraceState},
+        KeyValue,
+    };
+
+    fn span_data(
+        span_id: u64,
+        resource: &Arc<sdk::Resource>,
+        library: sdk::InstrumentationLibrary,
+    ) -> SpanData {
+        SpanData {
+            span_context: SpanContext::new(
+                TraceId::from_u128(1),
+                SpanId::from_u64(span_id),
+                0,
+                false,
+                TraceState::default(),
+            ),
+            parent_span_id: SpanId::invalid(),
+            span_kind: SpanKind::Internal,
+            name: span_id.to_string(),
+            start_time: SystemTime::UNIX_EPOCH,
+            end_time: SystemTime::UNIX_EPOCH,
+            attributes: sdk::trace::EvictedHashMap::new(128, 0),
+            message_events: sdk::trace::EvictedQueue::new(128),
+            links: sdk::trace::EvictedQueue::new(128),
+            status_code: StatusCode::Unset,
+            status_message: String::new(),
+            resource: resource.clone(),
+            instrumentation_lib: library,
+        }
+    }
+
+    #[test]
+    fn groups_spans_by_resource_and_library() {
+        let tracebuild = Arc::new(sdk::Resource::new(vec![KeyValue::new(
+            "service.name",
+            "tracebuild",
+        )]));
+        let other = Arc::new(sdk::Resource::new(vec![KeyValue::new(
+            "service.name",
+            "other",
+        )]));
+        let same_as_tracebuild = Arc::new(sdk::Resource::new(vec![KeyValue::new(
+            "service.name",
+            "tracebuild",
+        )]));
+        let library = sdk::InstrumentationLibrary::new("tracebuild", Some("0.3.0"));
+        let other_library = sdk::InstrumentationLibrary::new("other", None);
+
+        let resource_spans = resource_spans(vec![
+            span_data(1, &tracebuild, library),
+            span_data(2, &other, library),
+            span_data(3, &same_as_tracebuild, other_library),
+            span_data(4, &tracebuild, library),
+        ]);
+
+        let grouped = resource_spans
+            .iter()
+            .map(|resource_spans| {
+                (
+                    resource_spans.resource.as_ref().unwrap().attributes.len(),
+                    resource_spans
+                        .instrumentation_library_spans
+                        .iter()
+                        .map(|library_spans| {
+                            let library = library_spans.instrumentation_library.as_ref().unwrap();
+                            (
+                                library.name.as_str(),
+                                library.version.as_str(),
+                                library_spans
+                                    .spans
+                                    .iter()
+                                    .map(|span| span.name.as_str())
+                                    .collect::<Vec<_>>(),
+                            )
+                        })
+                        .collect::<Vec<_>>(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            grouped,
+            vec![
+                (
+                    1,
+                    vec![
+                        ("tracebuild", "0.3.0", vec!["1", "4"]),
+                        ("other", "", vec!["3"]),
+                    ]
+                ),
+                (1, vec![("tracebuild", "0.3.0", vec!["2"])]),
+            ]
+        );
+    }
+}
@@ -0,0 +1,46 @@
+use super::proto::{self, collector::trace::v1::ExportTraceServiceRequest};
+use super::{json, Protocol, Signal};
+use async_trait::async_trait;
+use opentelemetry::{
+    sdk::export::trace::{ExportResult, SpanData, SpanExporter},
+    trace::TraceError,
+};
+
+#[derive(Debug)]
+pub(crate) struct OtlpHttpExporter {
+    agent: ureq::Agent,
+    endpoint: String,
+    protocol: Protocol,
+}
+
+#[async_trait]
+impl SpanExporter for OtlpHttpExporter {
+    async fn export(&mut self, batch: Vec<SpanData>) -> ExportResult {
+        let request = ExportTraceServiceRequest {
+            resource_spans: proto::resource_spans(batch),
+        };
+        let (content_type, body) =
+            super::http_body(self.protocol, &request, json::export_trace_service_request)?;
+
+        // ureq is blocking, so keep it off the async runtime's worker threads.
+        let agent = self.agent.clone();
+        let endpoint = self.endpoint.clone();
+        tokio::task::spawn_blocking(move || {
+            super::send_http(&agent, &endpoint, content_type, &body)
+        })
+        .await
+        .map_err(|err| TraceError::from(format!("Failed to send spans: {}", err)))?
+        .map_err(TraceError::from)
+    }
+}
+
+pub(crate) fn new_otlp_http_exporter(protocol: Protocol) -> OtlpHttpExporter {
+    let agent = ureq::AgentBuilder::new()
+        .timeout(super::timeout(Signal::Traces))
+        .build();
+    OtlpHttpExporter {
+        agent,
+        endpoint: super::endpoint(Signal::Traces, protocol),
+        protocol,
+    }
+}
@@ -18,26 +18,15 @@ impl Drop for PrometheusPushOnDropExporter {
 }
 
 pub(crate) fn new_prometheus_push_on_drop_exporter(
+    histogram_boundaries: Vec<f64>,
+    resource: opentelemetry::sdk::Resource,
 ) -> Result<PrometheusPushOnDropExporter, MetricsError> {
     let host = std::env::var("OTEL_EXPORTER_PROMETHEUS_HOST").unwrap_or_else(|_| "0.0.0.0".into());
     let port = std::env::var("OTEL_EXPORTER_PROMETHEUS_PORT").unwrap_or_else(|_| "9464".into());
     let endpoint = format!("{}:{}", host, port);
     let exporter = opentelemetry_prometheus::exporter()
-        .with_default_histogram_boundaries(vec![
-            1.,    // 1 sec
-            10.,   // 10 secs
-            30.,   // 30 secs
-            60.,   // 1 min
-            300.,  // 5 mins
-            600.,  // 10 mins
-            900.,  // 15 mins
-            1200., // 20 mins
-            1500., // 25 mins
-            1800., // 30 mins
-            2100., // 35 mins
-            2400., // 40 mins
-            2700., // 45 mins
-        ])
+        .with_default_histogram_boundaries(histogram_boundaries)
+        .with_resource(resource)
         .try_init()?;
     Ok(PrometheusPushOnDropExporter { exporter, endpoint })
 }
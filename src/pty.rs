@@ -0,0 +1,144 @@
+use crate::cmd::nix_to_io_error;
+use std::{
+    fs::File,
+    io::{self, Read as _},
+    os::unix::io::{AsRawFd, FromRawFd, RawFd},
+    pin::Pin,
+    process::Stdio,
+    task::{Context, Poll},
+};
+use tokio::io::{unix::AsyncFd, AsyncRead, ReadBuf};
+
+/// A pseudo-terminal. The child writes to the slave side, tracebuild reads from the master side.
+pub(crate) struct Pty {
+    master: PtyMaster,
+    slave: File,
+}
+
+impl Pty {
+    /// Opens a new pseudo-terminal with the same window size as the terminal tracebuild runs in,
+    /// if any. Output processing is disabled, so the child's output is captured and forwarded
+    /// unchanged instead of with newlines translated to CRLF.
+    pub(crate) fn open() -> io::Result<Self> {
+        use nix::{
+            fcntl::{fcntl, FcntlArg, FdFlag, OFlag},
+            sys::termios::{tcgetattr, tcsetattr, OutputFlags, SetArg},
+        };
+
+        let winsize = terminal_window_size();
+        let pty = nix::pty::openpty(winsize.as_ref(), None).map_err(nix_to_io_error)?;
+        // SAFETY: openpty returned new file descriptors, which are owned by nobody else.
+        let (master, slave) =
+            unsafe { (File::from_raw_fd(pty.master), File::from_raw_fd(pty.slave)) };
+        for fd in &[pty.master, pty.slave] {
+            fcntl(*fd, FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC)).map_err(nix_to_io_error)?;
+        }
+        fcntl(pty.master, FcntlArg::F_SETFL(OFlag::O_NONBLOCK)).map_err(nix_to_io_error)?;
+        let mut termios = tcgetattr(pty.slave).map_err(nix_to_io_error)?;
+        termios.output_flags.remove(OutputFlags::OPOST);
+        tcsetattr(pty.slave, SetArg::TCSANOW, &termios).map_err(nix_to_io_error)?;
+
+        Ok(Self {
+            master: PtyMaster(AsyncFd::new(master)?),
+            slave,
+        })
+    }
+
+    /// Returns a handle to the slave side, which can be used as stdout or stderr of the child.
+    pub(crate) fn slave_stdio(&self) -> io::Result<Stdio> {
+        Ok(self.slave.try_clone()?.into())
+    }
+
+    /// Starts copying window size changes of the terminal tracebuild runs in to the
+    /// pseudo-terminal.
+    pub(crate) fn forward_window_size(&self) -> io::Result<WindowSizeForwarder> {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        Ok(WindowSizeForwarder {
+            master: self.master.0.get_ref().try_clone()?,
+            window_change: signal(SignalKind::window_change())?,
+        })
+    }
+
+    /// Closes the slave side and returns the master side. Call after the child was spawned, so
+    /// reading reaches the end once the child and its descendants closed the terminal.
+    pub(crate) fn into_master(self) -> PtyMaster {
+        self.master
+    }
+}
+
+/// Reads the output written to a pseudo-terminal.
+pub(crate) struct PtyMaster(AsyncFd<File>);
+
+impl AsyncRead for PtyMaster {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        loop {
+            let mut guard = match self.0.poll_read_ready(cx) {
+                Poll::Ready(guard) => guard?,
+                Poll::Pending => return Poll::Pending,
+            };
+            match guard.try_io(|fd| fd.get_ref().read(buf.initialize_unfilled())) {
+                Ok(Ok(n)) => {
+                    buf.advance(n);
+                    return Poll::Ready(Ok(()));
+                }
+                // Linux reports EIO once all slave handles are closed.
+                Ok(Err(err)) if err.raw_os_error() == Some(libc::EIO) => {
+                    return Poll::Ready(Ok(()))
+                }
+                Ok(Err(err)) => return Poll::Ready(Err(err)),
+                Err(_would_block) => continue,
+            }
+        }
+    }
+}
+
+/// Copies the window size of the terminal tracebuild runs in to a pseudo-terminal whenever it
+/// changes.
+pub(crate) struct WindowSizeForwarder {
+    master: File,
+    window_change: tokio::signal::unix::Signal,
+}
+
+impl WindowSizeForwarder {
+    /// Waits for the next window size change and copies the new size. The processes in the
+    /// pseudo-terminal don't get a SIGWINCH, because it isn't their controlling terminal.
+    pub(crate) async fn recv(&mut self) -> io::Result<()> {
+        self.window_change.recv().await;
+        match terminal_window_size() {
+            // SAFETY: TIOCSWINSZ only reads the given winsize struct.
+            Some(winsize) => {
+                match unsafe { libc::ioctl(self.master.as_raw_fd(), libc::TIOCSWINSZ, &winsize) } {
+                    -1 => Err(io::Error::last_os_error()),
+                    _ => Ok(()),
+                }
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+/// Returns the window size of the terminal attached to stdout, stderr or stdin, in this order.
+fn terminal_window_size() -> Option<nix::pty::Winsize> {
+    [libc::STDOUT_FILENO, libc::STDERR_FILENO, libc::STDIN_FILENO]
+        .iter()
+        .find_map(|fd| window_size(*fd))
+}
+
+fn window_size(fd: RawFd) -> Option<nix::pty::Winsize> {
+    let mut winsize = nix::pty::Winsize {
+        ws_row: 0,
+        ws_col: 0,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    // SAFETY: TIOCGWINSZ only writes to the given winsize struct.
+    match unsafe { libc::ioctl(fd, libc::TIOCGWINSZ, &mut winsize) } {
+        -1 => None,
+        _ => Some(winsize),
+    }
+}
@@ -20,7 +20,7 @@ impl FromStr for Timestamp {
     type Err = Box<dyn std::error::Error>;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let secs_since_epoch = u64::from_str_radix(s, 10)?;
+        let secs_since_epoch = s.parse::<u64>()?;
         let since_epoch = Duration::from_secs(secs_since_epoch);
         Ok(Timestamp(
             SystemTime::UNIX_EPOCH